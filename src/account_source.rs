@@ -0,0 +1,480 @@
+use std::{cell::Cell, collections::HashMap, path::Path, str::FromStr, sync::Mutex};
+
+use async_trait::async_trait;
+use base64::{prelude::BASE64_STANDARD, Engine};
+use solana_account::{Account, AccountSharedData, ReadableAccount};
+use solana_account_decoder_client_types::{UiAccount, UiAccountData, UiAccountEncoding};
+use solana_client::{
+    nonblocking, rpc_client::RpcClient, rpc_config::RpcAccountInfoConfig,
+    rpc_response::RpcKeyedAccount, rpc_response::RpcResult,
+};
+use solana_clock::Slot;
+use solana_commitment_config::CommitmentConfig;
+use solana_pubkey::Pubkey;
+use solana_signer::signers::Signers;
+use solana_transaction::{versioned::VersionedTransaction, Transaction};
+
+use crate::{
+    config::LocalSimulationConfig, error::SolanaClientExtError, feature_set::MAX_MULTIPLE_ACCOUNTS,
+    local, report::SimulationReport, token,
+};
+
+/// # AccountSource
+///
+/// Provides the account state the local estimator executes transactions against.
+pub trait AccountSource {
+    /// Returns the accounts at `pubkeys`, in the same order, with `None` for accounts
+    /// that don't exist.
+    fn get_accounts(
+        &self,
+        pubkeys: &[Pubkey],
+    ) -> Result<Vec<Option<AccountSharedData>>, SolanaClientExtError>;
+}
+
+/// # RpcAccountSource
+///
+/// Reads accounts from the cluster in batches of `getMultipleAccounts` requests.
+///
+/// Every request is made at the slot of the first response or later,
+/// so the accounts read through the same source form a consistent snapshot.
+pub struct RpcAccountSource<'a> {
+    rpc_client: &'a RpcClient,
+    min_context_slot: Cell<Option<Slot>>,
+}
+
+impl<'a> RpcAccountSource<'a> {
+    pub fn new(rpc_client: &'a RpcClient) -> Self {
+        Self {
+            rpc_client,
+            min_context_slot: Cell::new(None),
+        }
+    }
+
+    /// Reads accounts at `min_context_slot` or later.
+    pub fn with_min_context_slot(rpc_client: &'a RpcClient, min_context_slot: Slot) -> Self {
+        Self {
+            rpc_client,
+            min_context_slot: Cell::new(Some(min_context_slot)),
+        }
+    }
+}
+
+impl AccountSource for RpcAccountSource<'_> {
+    fn get_accounts(
+        &self,
+        pubkeys: &[Pubkey],
+    ) -> Result<Vec<Option<AccountSharedData>>, SolanaClientExtError> {
+        get_accounts_in_chunks(pubkeys, |chunk| {
+            let mut min_context_slot = self.min_context_slot.get();
+            let response = self.rpc_client.get_multiple_accounts_with_config(
+                chunk,
+                accounts_config(self.rpc_client.commitment(), min_context_slot),
+            );
+            let accounts = fetched_accounts(response, &mut min_context_slot)?.collect();
+            self.min_context_slot.set(min_context_slot);
+            Ok(accounts)
+        })
+    }
+}
+
+/// # AsyncAccountSource
+///
+/// Same as [`AccountSource`], for sources read asynchronously.
+#[async_trait]
+pub trait AsyncAccountSource: Sync {
+    /// Returns the accounts at `pubkeys`, in the same order, with `None` for accounts
+    /// that don't exist.
+    async fn get_accounts(
+        &self,
+        pubkeys: &[Pubkey],
+    ) -> Result<Vec<Option<AccountSharedData>>, SolanaClientExtError>;
+}
+
+/// # AsyncRpcAccountSource
+///
+/// Same as [`RpcAccountSource`], with the nonblocking client.
+pub struct AsyncRpcAccountSource<'a> {
+    rpc_client: &'a nonblocking::rpc_client::RpcClient,
+    min_context_slot: Mutex<Option<Slot>>,
+}
+
+impl<'a> AsyncRpcAccountSource<'a> {
+    pub fn new(rpc_client: &'a nonblocking::rpc_client::RpcClient) -> Self {
+        Self {
+            rpc_client,
+            min_context_slot: Mutex::new(None),
+        }
+    }
+
+    /// Reads accounts at `min_context_slot` or later.
+    pub fn with_min_context_slot(
+        rpc_client: &'a nonblocking::rpc_client::RpcClient,
+        min_context_slot: Slot,
+    ) -> Self {
+        Self {
+            rpc_client,
+            min_context_slot: Mutex::new(Some(min_context_slot)),
+        }
+    }
+}
+
+#[async_trait]
+impl AsyncAccountSource for AsyncRpcAccountSource<'_> {
+    async fn get_accounts(
+        &self,
+        pubkeys: &[Pubkey],
+    ) -> Result<Vec<Option<AccountSharedData>>, SolanaClientExtError> {
+        let mut accounts = Vec::with_capacity(pubkeys.len());
+        for chunk in pubkeys.chunks(MAX_MULTIPLE_ACCOUNTS) {
+            let mut min_context_slot = *self.min_context_slot.lock().unwrap();
+            let response = self
+                .rpc_client
+                .get_multiple_accounts_with_config(
+                    chunk,
+                    accounts_config(self.rpc_client.commitment(), min_context_slot),
+                )
+                .await;
+            accounts.extend(fetched_accounts(response, &mut min_context_slot)?);
+            *self.min_context_slot.lock().unwrap() = min_context_slot;
+        }
+        Ok(accounts)
+    }
+}
+
+/// # AccountSnapshot
+///
+/// Accounts held in memory, for estimating without network access.
+///
+/// Snapshots can be saved to and loaded from JSON, as a list of
+/// `{ "pubkey": ..., "account": ... }` objects in the format of the `getProgramAccounts`
+/// RPC method. `solana account --output json` prints a single such object, which has
+/// to be wrapped in a list.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AccountSnapshot {
+    accounts: HashMap<Pubkey, AccountSharedData>,
+}
+
+impl AccountSnapshot {
+    pub fn new(accounts: impl IntoIterator<Item = (Pubkey, AccountSharedData)>) -> Self {
+        Self {
+            accounts: accounts.into_iter().collect(),
+        }
+    }
+
+    /// Reads `pubkeys` from `source` into a snapshot, leaving out accounts that don't exist.
+    pub fn capture(
+        source: &(impl AccountSource + ?Sized),
+        pubkeys: &[Pubkey],
+    ) -> Result<Self, SolanaClientExtError> {
+        let accounts = source.get_accounts(pubkeys)?;
+        Ok(Self::new(pubkeys.iter().zip(accounts).filter_map(
+            |(pubkey, account)| account.map(|account| (*pubkey, account)),
+        )))
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, SolanaClientExtError> {
+        let keyed_accounts: Vec<RpcKeyedAccount> = serde_json::from_str(json)
+            .map_err(|err| SolanaClientExtError::AccountSnapshotError(err.to_string()))?;
+        keyed_accounts
+            .into_iter()
+            .map(|keyed_account| {
+                let pubkey = Pubkey::from_str(&keyed_account.pubkey).map_err(|err| {
+                    SolanaClientExtError::AccountSnapshotError(format!(
+                        "{}: {err}",
+                        keyed_account.pubkey
+                    ))
+                })?;
+                let account = keyed_account.account.decode().ok_or_else(|| {
+                    SolanaClientExtError::AccountSnapshotError(format!(
+                        "Unable to decode account {pubkey}."
+                    ))
+                })?;
+                Ok((pubkey, account))
+            })
+            .collect()
+    }
+
+    pub fn from_json_file(path: impl AsRef<Path>) -> Result<Self, SolanaClientExtError> {
+        let path = path.as_ref();
+        let json = std::fs::read_to_string(path).map_err(|err| {
+            SolanaClientExtError::AccountSnapshotError(format!("{}: {err}", path.display()))
+        })?;
+        Self::from_json(&json)
+    }
+
+    /// Serializes the snapshot in the format read by [`AccountSnapshot::from_json`],
+    /// with account data encoded as base64.
+    pub fn to_json(&self) -> Result<String, SolanaClientExtError> {
+        let keyed_accounts = self
+            .accounts
+            .iter()
+            .map(|(pubkey, account)| RpcKeyedAccount {
+                pubkey: pubkey.to_string(),
+                account: UiAccount {
+                    lamports: account.lamports(),
+                    data: UiAccountData::Binary(
+                        BASE64_STANDARD.encode(account.data()),
+                        UiAccountEncoding::Base64,
+                    ),
+                    owner: account.owner().to_string(),
+                    executable: account.executable(),
+                    rent_epoch: account.rent_epoch(),
+                    space: Some(account.data().len() as u64),
+                },
+            })
+            .collect::<Vec<_>>();
+        serde_json::to_string_pretty(&keyed_accounts)
+            .map_err(|err| SolanaClientExtError::AccountSnapshotError(err.to_string()))
+    }
+
+    pub fn to_json_file(&self, path: impl AsRef<Path>) -> Result<(), SolanaClientExtError> {
+        let path = path.as_ref();
+        std::fs::write(path, self.to_json()?).map_err(|err| {
+            SolanaClientExtError::AccountSnapshotError(format!("{}: {err}", path.display()))
+        })
+    }
+
+    pub fn get(&self, pubkey: &Pubkey) -> Option<&AccountSharedData> {
+        self.accounts.get(pubkey)
+    }
+
+    pub fn insert(&mut self, pubkey: Pubkey, account: AccountSharedData) {
+        self.accounts.insert(pubkey, account);
+    }
+}
+
+impl FromIterator<(Pubkey, AccountSharedData)> for AccountSnapshot {
+    fn from_iter<T: IntoIterator<Item = (Pubkey, AccountSharedData)>>(iter: T) -> Self {
+        Self::new(iter)
+    }
+}
+
+impl AccountSource for AccountSnapshot {
+    fn get_accounts(
+        &self,
+        pubkeys: &[Pubkey],
+    ) -> Result<Vec<Option<AccountSharedData>>, SolanaClientExtError> {
+        Ok(pubkeys
+            .iter()
+            .map(|pubkey| self.accounts.get(pubkey).cloned())
+            .collect())
+    }
+}
+
+/// Executes `transaction` in a local SVM instance against the accounts of `source`,
+/// without any network access unless `source` reads from the cluster.
+///
+/// Programs, address lookup tables and sysvars are read from `source` as well.
+/// [`FeatureSetSource::Cluster`](crate::FeatureSetSource::Cluster) activates the features
+/// whose accounts `source` holds, so snapshots without feature accounts should be simulated
+/// with [`FeatureSetSource::AllEnabled`](crate::FeatureSetSource::AllEnabled) or a custom
+/// feature set.
+pub fn simulate_unsigned_tx_offline<I: Signers + ?Sized>(
+    source: &(impl AccountSource + ?Sized),
+    transaction: &Transaction,
+    signers: &I,
+    config: &LocalSimulationConfig,
+) -> Result<SimulationReport, Box<dyn std::error::Error + 'static>> {
+    Ok(local::simulate_transaction_with_source(
+        source,
+        &VersionedTransaction::from(transaction.clone()),
+        &signers.try_pubkeys()?,
+        config,
+        None,
+    )?)
+}
+
+/// Same as [`simulate_unsigned_tx_offline`], returning the consumed compute units
+/// of a successful transaction.
+pub fn estimate_compute_units_unsigned_tx_offline<I: Signers + ?Sized>(
+    source: &(impl AccountSource + ?Sized),
+    transaction: &Transaction,
+    signers: &I,
+    config: &LocalSimulationConfig,
+) -> Result<u64, Box<dyn std::error::Error + 'static>> {
+    let report = simulate_unsigned_tx_offline(source, transaction, signers, config)?;
+    report.check()?;
+
+    Ok(report.units_consumed)
+}
+
+/// Message accounts and the mints of their token accounts.
+pub(crate) type MessageAccounts = (
+    Vec<(Pubkey, AccountSharedData)>,
+    HashMap<Pubkey, AccountSharedData>,
+);
+
+/// Reads the message accounts at `pubkeys` from `source`, with empty default accounts
+/// for those that don't exist, along with the mints of their token accounts
+/// which aren't message accounts themselves.
+pub(crate) fn message_accounts(
+    source: &(impl AccountSource + ?Sized),
+    pubkeys: &[Pubkey],
+) -> Result<MessageAccounts, SolanaClientExtError> {
+    let accounts = default_accounts(pubkeys, source.get_accounts(pubkeys)?);
+    let mint_addresses = token::missing_mints(&accounts);
+    let mints = existing_accounts(&mint_addresses, source.get_accounts(&mint_addresses)?);
+    Ok((accounts, mints))
+}
+
+/// Same as [`message_accounts`], reading the accounts from an [`AsyncAccountSource`].
+pub(crate) async fn message_accounts_async(
+    source: &(impl AsyncAccountSource + ?Sized),
+    pubkeys: &[Pubkey],
+) -> Result<MessageAccounts, SolanaClientExtError> {
+    let accounts = default_accounts(pubkeys, source.get_accounts(pubkeys).await?);
+    let mint_addresses = token::missing_mints(&accounts);
+    let mints = existing_accounts(&mint_addresses, source.get_accounts(&mint_addresses).await?);
+    Ok((accounts, mints))
+}
+
+/// Pairs `pubkeys` with the accounts read for them, with empty default accounts
+/// for those that don't exist.
+pub(crate) fn default_accounts(
+    pubkeys: &[Pubkey],
+    accounts: Vec<Option<AccountSharedData>>,
+) -> Vec<(Pubkey, AccountSharedData)> {
+    pubkeys
+        .iter()
+        .copied()
+        .zip(accounts.into_iter().map(Option::unwrap_or_default))
+        .collect()
+}
+
+/// Pairs `pubkeys` with the accounts read for them, leaving out those that don't exist.
+pub(crate) fn existing_accounts(
+    pubkeys: &[Pubkey],
+    accounts: Vec<Option<AccountSharedData>>,
+) -> HashMap<Pubkey, AccountSharedData> {
+    pubkeys
+        .iter()
+        .copied()
+        .zip(accounts)
+        .filter_map(|(pubkey, account)| Some((pubkey, account?)))
+        .collect()
+}
+
+/// Reads `pubkeys` with `get_chunk` in chunks of at most [`MAX_MULTIPLE_ACCOUNTS`] keys,
+/// the most a `getMultipleAccounts` request takes, and returns the accounts in order.
+fn get_accounts_in_chunks(
+    pubkeys: &[Pubkey],
+    mut get_chunk: impl FnMut(&[Pubkey]) -> Result<Vec<Option<AccountSharedData>>, SolanaClientExtError>,
+) -> Result<Vec<Option<AccountSharedData>>, SolanaClientExtError> {
+    let mut accounts = Vec::with_capacity(pubkeys.len());
+    for chunk in pubkeys.chunks(MAX_MULTIPLE_ACCOUNTS) {
+        accounts.extend(get_chunk(chunk)?);
+    }
+    Ok(accounts)
+}
+
+fn accounts_config(
+    commitment: CommitmentConfig,
+    min_context_slot: Option<Slot>,
+) -> RpcAccountInfoConfig {
+    RpcAccountInfoConfig {
+        encoding: Some(UiAccountEncoding::Base64),
+        commitment: Some(commitment),
+        data_slice: None,
+        min_context_slot,
+    }
+}
+
+/// Unwraps a `getMultipleAccounts` response and pins `min_context_slot` to its slot
+/// if it isn't set yet.
+fn fetched_accounts(
+    response: RpcResult<Vec<Option<Account>>>,
+    min_context_slot: &mut Option<Slot>,
+) -> Result<impl Iterator<Item = Option<AccountSharedData>>, SolanaClientExtError> {
+    let response =
+        response.map_err(|err| SolanaClientExtError::AccountFetchError(err.to_string()))?;
+    min_context_slot.get_or_insert(response.context.slot);
+    Ok(response
+        .value
+        .into_iter()
+        .map(|account| account.map(AccountSharedData::from)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use solana_sdk::signature::Keypair;
+
+    use super::*;
+    use crate::{local::tests::transfer_snapshot, FeatureSetSource};
+
+    /// Snapshot recording the number of accounts of every read.
+    struct RecordingSource {
+        snapshot: AccountSnapshot,
+        reads: RefCell<Vec<usize>>,
+    }
+
+    impl AccountSource for RecordingSource {
+        fn get_accounts(
+            &self,
+            pubkeys: &[Pubkey],
+        ) -> Result<Vec<Option<AccountSharedData>>, SolanaClientExtError> {
+            self.reads.borrow_mut().push(pubkeys.len());
+            self.snapshot.get_accounts(pubkeys)
+        }
+    }
+
+    #[test]
+    fn reads_accounts_in_chunks() {
+        let pubkeys = (0..250).map(|_| Pubkey::new_unique()).collect::<Vec<_>>();
+        let source = RecordingSource {
+            snapshot: AccountSnapshot::new(pubkeys.iter().enumerate().map(|(index, pubkey)| {
+                (
+                    *pubkey,
+                    AccountSharedData::new(index as u64 + 1, 0, &Pubkey::default()),
+                )
+            })),
+            reads: RefCell::default(),
+        };
+
+        let accounts =
+            get_accounts_in_chunks(&pubkeys, |chunk| source.get_accounts(chunk)).unwrap();
+        assert_eq!(*source.reads.borrow(), [100, 100, 50]);
+        let lamports = accounts
+            .iter()
+            .map(|account| account.as_ref().unwrap().lamports())
+            .collect::<Vec<_>>();
+        assert_eq!(lamports, (1..=250).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn simulates_from_snapshot() {
+        let payer = Keypair::new();
+        let (snapshot, transaction) = transfer_snapshot(&payer);
+        let config = LocalSimulationConfig {
+            feature_set: FeatureSetSource::AllEnabled,
+            ..LocalSimulationConfig::default()
+        };
+
+        let units_consumed =
+            estimate_compute_units_unsigned_tx_offline(&snapshot, &transaction, &[&payer], &config)
+                .unwrap();
+        assert_eq!(units_consumed, 150);
+    }
+
+    #[test]
+    fn snapshot_json_round_trip() {
+        let pubkey = Pubkey::new_unique();
+        let account = AccountSharedData::from(Account {
+            lamports: 1_000,
+            data: vec![1, 2, 3],
+            owner: Pubkey::new_unique(),
+            executable: false,
+            rent_epoch: u64::MAX,
+        });
+        let snapshot = AccountSnapshot::new([(pubkey, account.clone())]);
+
+        let snapshot = AccountSnapshot::from_json(&snapshot.to_json().unwrap()).unwrap();
+        assert_eq!(
+            snapshot
+                .get_accounts(&[pubkey, Pubkey::new_unique()])
+                .unwrap(),
+            vec![Some(account), None]
+        );
+    }
+}
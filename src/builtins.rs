@@ -0,0 +1,156 @@
+//! Registry of the builtin programs available to the local estimator.
+
+use std::sync::Arc;
+
+use agave_feature_set::FeatureSet;
+use solana_precompile_error::PrecompileError;
+use solana_program_runtime::{
+    invoke_context::BuiltinFunctionWithContext,
+    loaded_programs::{ProgramCacheEntry, ProgramCacheForTxBatch},
+};
+use solana_pubkey::Pubkey;
+use solana_sdk_ids::{
+    bpf_loader, bpf_loader_deprecated, bpf_loader_upgradeable, compute_budget, loader_v4, stake,
+    system_program, vote,
+};
+use solana_svm_callback::InvokeContextCallback;
+
+/// A builtin program as registered by a validator.
+pub struct BuiltinPrototype {
+    pub name: &'static str,
+    pub program_id: Pubkey,
+    /// Compute units the builtin charges for every instruction it processes.
+    pub compute_units: u64,
+    pub entrypoint: BuiltinFunctionWithContext,
+}
+
+/// Builtins registered in the local program cache, with the compute units
+/// a validator charges for each of their instructions.
+///
+/// The address lookup table program isn't listed, it was migrated to sBPF
+/// and is loaded from its on-chain program account. Its native implementation
+/// has no 2.3 release, so lookup table instructions fail with an unsupported program
+/// error on snapshots or clusters where the program is still native.
+pub static BUILTINS: &[BuiltinPrototype] = &[
+    BuiltinPrototype {
+        name: "system_program",
+        program_id: system_program::id(),
+        compute_units: 150,
+        entrypoint: solana_system_program::system_processor::Entrypoint::vm,
+    },
+    BuiltinPrototype {
+        name: "compute_budget_program",
+        program_id: compute_budget::id(),
+        compute_units: 150,
+        entrypoint: solana_compute_budget_program::Entrypoint::vm,
+    },
+    BuiltinPrototype {
+        name: "solana_bpf_loader_deprecated_program",
+        program_id: bpf_loader_deprecated::id(),
+        compute_units: 1_140,
+        entrypoint: solana_bpf_loader_program::Entrypoint::vm,
+    },
+    BuiltinPrototype {
+        name: "solana_bpf_loader_program",
+        program_id: bpf_loader::id(),
+        compute_units: 570,
+        entrypoint: solana_bpf_loader_program::Entrypoint::vm,
+    },
+    BuiltinPrototype {
+        name: "solana_bpf_loader_upgradeable_program",
+        program_id: bpf_loader_upgradeable::id(),
+        compute_units: 2_370,
+        entrypoint: solana_bpf_loader_program::Entrypoint::vm,
+    },
+    BuiltinPrototype {
+        name: "loader_v4",
+        program_id: loader_v4::id(),
+        compute_units: 2_000,
+        entrypoint: solana_loader_v4_program::Entrypoint::vm,
+    },
+    BuiltinPrototype {
+        name: "stake_program",
+        program_id: stake::id(),
+        compute_units: 750,
+        entrypoint: solana_stake_program::stake_instruction::Entrypoint::vm,
+    },
+    BuiltinPrototype {
+        name: "vote_program",
+        program_id: vote::id(),
+        compute_units: 2_100,
+        entrypoint: solana_vote_program::vote_processor::Entrypoint::vm,
+    },
+];
+
+/// Returns the compute units charged by the builtin with the given program id.
+pub fn builtin_compute_units(program_id: &Pubkey) -> Option<u64> {
+    BUILTINS
+        .iter()
+        .find(|builtin| builtin.program_id == *program_id)
+        .map(|builtin| builtin.compute_units)
+}
+
+/// Precompiles enabled by the feature set, exposed to the invoke context.
+///
+/// Precompiles aren't programs of the program cache, the message processor
+/// verifies their instructions through these callbacks instead.
+pub(crate) struct Precompiles<'a>(pub &'a FeatureSet);
+
+impl InvokeContextCallback for Precompiles<'_> {
+    fn is_precompile(&self, program_id: &Pubkey) -> bool {
+        agave_precompiles::is_precompile(program_id, |feature_id| self.0.is_active(feature_id))
+    }
+
+    fn process_precompile(
+        &self,
+        program_id: &Pubkey,
+        data: &[u8],
+        instruction_datas: Vec<&[u8]>,
+    ) -> Result<(), PrecompileError> {
+        agave_precompiles::get_precompile(program_id, |feature_id| self.0.is_active(feature_id))
+            .ok_or(PrecompileError::InvalidPublicKey)?
+            .verify(data, &instruction_datas, self.0)
+    }
+}
+
+/// Inserts every builtin into the program cache.
+///
+/// Builtins that were migrated to sBPF on the cluster are overwritten
+/// when their on-chain program is loaded afterwards.
+pub(crate) fn register_builtins(program_cache: &mut ProgramCacheForTxBatch) {
+    for builtin in BUILTINS {
+        program_cache.replenish(
+            builtin.program_id,
+            Arc::new(ProgramCacheEntry::new_builtin(
+                0,
+                builtin.name.len(),
+                builtin.entrypoint,
+            )),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builtins_are_unique() {
+        for (i, builtin) in BUILTINS.iter().enumerate() {
+            assert!(BUILTINS[i + 1..]
+                .iter()
+                .all(|other| other.program_id != builtin.program_id));
+        }
+    }
+
+    #[test]
+    fn registers_every_builtin() {
+        let mut program_cache = ProgramCacheForTxBatch::default();
+        register_builtins(&mut program_cache);
+        for builtin in BUILTINS {
+            assert!(program_cache.find(&builtin.program_id).is_some());
+        }
+        assert_eq!(builtin_compute_units(&system_program::id()), Some(150));
+        assert_eq!(builtin_compute_units(&Pubkey::new_unique()), None);
+    }
+}
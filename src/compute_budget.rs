@@ -0,0 +1,349 @@
+//! Helpers to set ComputeBudget instructions on a message.
+
+use solana_compute_budget_interface::ComputeBudgetInstruction;
+use solana_instruction::{AccountMeta, Instruction};
+use solana_message::{
+    compiled_instruction::CompiledInstruction, v0, AddressLookupTableAccount, Message,
+    MessageHeader, VersionedMessage,
+};
+use solana_pubkey::Pubkey;
+use solana_sdk_ids::compute_budget;
+
+use crate::{
+    config::ComputeUnitMarginPolicy, error::SolanaClientExtError, lookup_tables,
+    report::ComputeUnitLimit,
+};
+
+/// Sets a ComputeBudget `instruction` on the message.
+///
+/// An existing ComputeBudget instruction of the same kind is updated in place,
+/// otherwise `instruction` is prepended to the instructions.
+/// The message is then compiled again, so it is exactly what `Message::new`
+/// would produce from the resulting instructions.
+///
+/// Fails without changing the message if an instruction references an account
+/// outside of the account keys, or if compiling again would drop an account key
+/// that no instruction references, such as an extra signer.
+pub(crate) fn set_compute_budget_instruction(
+    message: &mut Message,
+    instruction: Instruction,
+) -> Result<(), SolanaClientExtError> {
+    let mut instructions = decompile_instructions(
+        &message.instructions,
+        &message.account_keys,
+        |index| message.is_signer(index),
+        |index| is_writable_index(&message.header, message.account_keys.len(), index),
+    )?;
+    upsert_instruction(&mut instructions, instruction);
+
+    let payer = (message.header.num_required_signatures > 0).then(|| message.account_keys[0]);
+    let compiled =
+        Message::new_with_blockhash(&instructions, payer.as_ref(), &message.recent_blockhash);
+    check_account_keys(&message.account_keys, &compiled.account_keys)?;
+    *message = compiled;
+    Ok(())
+}
+
+/// Same as [`set_compute_budget_instruction`] for versioned messages.
+///
+/// v0 messages are compiled again with `lookup_tables`, which must hold every table
+/// the message loads addresses from.
+pub(crate) fn set_versioned_compute_budget_instruction(
+    message: &mut VersionedMessage,
+    instruction: Instruction,
+    lookup_tables: &[AddressLookupTableAccount],
+) -> Result<(), SolanaClientExtError> {
+    let v0_message = match message {
+        VersionedMessage::Legacy(message) => {
+            return set_compute_budget_instruction(message, instruction);
+        }
+        VersionedMessage::V0(message) => message,
+    };
+
+    let loaded_addresses =
+        lookup_tables::loaded_addresses(&VersionedMessage::V0(v0_message.clone()), lookup_tables)?;
+    let num_static_keys = v0_message.account_keys.len();
+    let num_writable_keys = num_static_keys + loaded_addresses.writable.len();
+    let account_keys = v0_message
+        .account_keys
+        .iter()
+        .chain(&loaded_addresses.writable)
+        .chain(&loaded_addresses.readonly)
+        .copied()
+        .collect::<Vec<_>>();
+    let mut instructions = decompile_instructions(
+        &v0_message.instructions,
+        &account_keys,
+        |index| index < usize::from(v0_message.header.num_required_signatures),
+        |index| {
+            if index < num_static_keys {
+                is_writable_index(&v0_message.header, num_static_keys, index)
+            } else {
+                index < num_writable_keys
+            }
+        },
+    )?;
+    upsert_instruction(&mut instructions, instruction);
+
+    let payer = v0_message.account_keys.first().copied().unwrap_or_default();
+    let compiled = v0::Message::try_compile(
+        &payer,
+        &instructions,
+        lookup_tables,
+        v0_message.recent_blockhash,
+    )
+    .map_err(|err| SolanaClientExtError::CompileError(err.to_string()))?;
+    check_account_keys(&v0_message.account_keys, &compiled.account_keys)?;
+    *v0_message = compiled;
+    Ok(())
+}
+
+/// Sets the compute unit limit of the message to `units_consumed` with the `margin` applied.
+pub(crate) fn set_compute_unit_limit(
+    message: &mut Message,
+    units_consumed: u64,
+    margin: &ComputeUnitMarginPolicy,
+) -> Result<ComputeUnitLimit, SolanaClientExtError> {
+    let limit = compute_unit_limit(units_consumed, margin)?;
+    set_compute_budget_instruction(
+        message,
+        ComputeBudgetInstruction::set_compute_unit_limit(limit.limit),
+    )?;
+    Ok(limit)
+}
+
+/// Same as [`set_compute_unit_limit`] for versioned messages.
+pub(crate) fn set_versioned_compute_unit_limit(
+    message: &mut VersionedMessage,
+    units_consumed: u64,
+    margin: &ComputeUnitMarginPolicy,
+    lookup_tables: &[AddressLookupTableAccount],
+) -> Result<ComputeUnitLimit, SolanaClientExtError> {
+    let limit = compute_unit_limit(units_consumed, margin)?;
+    set_versioned_compute_budget_instruction(
+        message,
+        ComputeBudgetInstruction::set_compute_unit_limit(limit.limit),
+        lookup_tables,
+    )?;
+    Ok(limit)
+}
+
+fn compute_unit_limit(
+    units_consumed: u64,
+    margin: &ComputeUnitMarginPolicy,
+) -> Result<ComputeUnitLimit, SolanaClientExtError> {
+    let units_consumed = u32::try_from(units_consumed).map_err(|_| {
+        SolanaClientExtError::ComputeUnitsError(format!(
+            "{units_consumed} compute units exceed the limit of a transaction."
+        ))
+    })?;
+    Ok(ComputeUnitLimit {
+        units_consumed,
+        limit: margin.compute_unit_limit(units_consumed),
+    })
+}
+
+/// Updates the ComputeBudget instruction of the same kind as `instruction`,
+/// or prepends `instruction` if there is none.
+fn upsert_instruction(instructions: &mut Vec<Instruction>, instruction: Instruction) {
+    let existing = instructions.iter_mut().find(|existing| {
+        compute_budget::check_id(&existing.program_id)
+            && existing.data.first() == instruction.data.first()
+    });
+    match existing {
+        Some(existing) => existing.data = instruction.data,
+        None => instructions.insert(0, instruction),
+    }
+}
+
+/// Rebuilds compiled instructions referencing `account_keys`.
+fn decompile_instructions(
+    instructions: &[CompiledInstruction],
+    account_keys: &[Pubkey],
+    is_signer: impl Fn(usize) -> bool,
+    is_writable: impl Fn(usize) -> bool,
+) -> Result<Vec<Instruction>, SolanaClientExtError> {
+    let account_key = |instruction_index: usize, index: u8| {
+        account_keys
+            .get(usize::from(index))
+            .copied()
+            .ok_or_else(|| {
+                SolanaClientExtError::CompileError(format!(
+                    "Instruction {instruction_index} references account index {index}, \
+                 but the message has {} account keys.",
+                    account_keys.len()
+                ))
+            })
+    };
+    instructions
+        .iter()
+        .enumerate()
+        .map(|(instruction_index, compiled_ix)| {
+            Ok(Instruction {
+                program_id: account_key(instruction_index, compiled_ix.program_id_index)?,
+                accounts: compiled_ix
+                    .accounts
+                    .iter()
+                    .map(|index| {
+                        Ok(AccountMeta {
+                            pubkey: account_key(instruction_index, *index)?,
+                            is_signer: is_signer(usize::from(*index)),
+                            is_writable: is_writable(usize::from(*index)),
+                        })
+                    })
+                    .collect::<Result<_, SolanaClientExtError>>()?,
+                data: compiled_ix.data.clone(),
+            })
+        })
+        .collect()
+}
+
+/// Checks that the compiled message kept every account key of the original message.
+fn check_account_keys(
+    original: &[Pubkey],
+    compiled: &[Pubkey],
+) -> Result<(), SolanaClientExtError> {
+    let dropped = original
+        .iter()
+        .filter(|key| !compiled.contains(key))
+        .map(ToString::to_string)
+        .collect::<Vec<_>>();
+    if dropped.is_empty() {
+        Ok(())
+    } else {
+        Err(SolanaClientExtError::CompileError(format!(
+            "No instruction references the account keys {}, compiling the message would drop them.",
+            dropped.join(", ")
+        )))
+    }
+}
+
+/// Returns true if the static account at `index` was requested to be writable by the header.
+fn is_writable_index(header: &MessageHeader, num_static_keys: usize, index: usize) -> bool {
+    let num_required_signatures = usize::from(header.num_required_signatures);
+    if index < num_required_signatures {
+        index
+            < num_required_signatures
+                .saturating_sub(usize::from(header.num_readonly_signed_accounts))
+    } else {
+        index < num_static_keys.saturating_sub(usize::from(header.num_readonly_unsigned_accounts))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_hash::Hash;
+
+    use super::*;
+
+    #[test]
+    fn updates_existing_limit() {
+        let payer = Pubkey::new_unique();
+        let instruction = Instruction::new_with_bytes(
+            Pubkey::new_unique(),
+            &[1],
+            vec![
+                AccountMeta::new(Pubkey::new_unique(), false),
+                AccountMeta::new_readonly(Pubkey::new_unique(), true),
+            ],
+        );
+        let mut message = Message::new(std::slice::from_ref(&instruction), Some(&payer));
+
+        set_compute_budget_instruction(
+            &mut message,
+            ComputeBudgetInstruction::set_compute_unit_limit(1_000),
+        )
+        .unwrap();
+        set_compute_budget_instruction(
+            &mut message,
+            ComputeBudgetInstruction::set_compute_unit_limit(2_000),
+        )
+        .unwrap();
+
+        let expected = Message::new(
+            &[
+                ComputeBudgetInstruction::set_compute_unit_limit(2_000),
+                instruction,
+            ],
+            Some(&payer),
+        );
+        assert_eq!(message, expected);
+    }
+
+    #[test]
+    fn rejects_dropping_unreferenced_signer() {
+        let payer = Pubkey::new_unique();
+        let instruction = Instruction::new_with_bytes(Pubkey::new_unique(), &[1], vec![]);
+        let mut message = Message::new(&[instruction], Some(&payer));
+        let signer = Pubkey::new_unique();
+        message.account_keys.insert(1, signer);
+        message.header.num_required_signatures += 1;
+        message.instructions[0].program_id_index += 1;
+        let original = message.clone();
+
+        let err = set_compute_budget_instruction(
+            &mut message,
+            ComputeBudgetInstruction::set_compute_unit_limit(1_000),
+        )
+        .unwrap_err();
+        assert!(
+            matches!(err, SolanaClientExtError::CompileError(ref err) if err.contains(&signer.to_string()))
+        );
+        assert_eq!(message, original);
+    }
+
+    #[test]
+    fn rejects_out_of_range_account_index() {
+        let payer = Pubkey::new_unique();
+        let instruction = Instruction::new_with_bytes(Pubkey::new_unique(), &[1], vec![]);
+        let mut message = Message::new(&[instruction], Some(&payer));
+        message.instructions[0].accounts.push(u8::MAX);
+
+        let err = set_compute_budget_instruction(
+            &mut message,
+            ComputeBudgetInstruction::set_compute_unit_limit(1_000),
+        )
+        .unwrap_err();
+        assert!(matches!(err, SolanaClientExtError::CompileError(_)));
+    }
+
+    #[test]
+    fn updates_existing_limit_in_v0_message() {
+        let payer = Pubkey::new_unique();
+        let lookup_table = AddressLookupTableAccount {
+            key: Pubkey::new_unique(),
+            addresses: vec![Pubkey::new_unique(), Pubkey::new_unique()],
+        };
+        let instruction = Instruction::new_with_bytes(
+            Pubkey::new_unique(),
+            &[1],
+            vec![
+                AccountMeta::new(lookup_table.addresses[0], false),
+                AccountMeta::new_readonly(lookup_table.addresses[1], false),
+            ],
+        );
+        let lookup_tables = [lookup_table];
+        let compile = |instructions: &[Instruction]| {
+            VersionedMessage::V0(
+                v0::Message::try_compile(&payer, instructions, &lookup_tables, Hash::default())
+                    .unwrap(),
+            )
+        };
+        let mut message = compile(std::slice::from_ref(&instruction));
+
+        for units in [1_000, 2_000] {
+            set_versioned_compute_budget_instruction(
+                &mut message,
+                ComputeBudgetInstruction::set_compute_unit_limit(units),
+                &lookup_tables,
+            )
+            .unwrap();
+        }
+
+        let expected = compile(&[
+            ComputeBudgetInstruction::set_compute_unit_limit(2_000),
+            instruction,
+        ]);
+        assert_eq!(message, expected);
+    }
+}
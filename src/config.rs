@@ -0,0 +1,269 @@
+use std::{cell::RefCell, collections::HashMap, fmt::Debug, rc::Rc, sync::Arc};
+
+use solana_account::{AccountSharedData, WritableAccount};
+use solana_log_collector::LogCollector;
+
+use solana_program_runtime::execution_budget::MAX_COMPUTE_UNIT_LIMIT;
+use solana_pubkey::Pubkey;
+
+use crate::{feature_set::FeatureSetSource, sysvars::Sysvars};
+
+/// # LocalSimulationConfig
+///
+/// Configures how `estimate_compute_units_unsigned_tx_with_config` builds
+/// the environment the transaction is executed in.
+#[derive(Clone, Debug, Default)]
+pub struct LocalSimulationConfig {
+    /// Sysvars exposed to programs. Fetched from the cluster when `None`.
+    pub sysvars: Option<Sysvars>,
+    /// Features active during execution. Defaults to the cluster's feature set.
+    pub feature_set: FeatureSetSource,
+    /// Pubkeys signing the transaction later, in addition to the signers passed along.
+    pub declared_signers: Vec<Pubkey>,
+    /// Add the cost of verifying the transaction signatures to the consumed compute units.
+    pub charge_signature_verification: bool,
+    /// Replaces the state of accounts before execution, to simulate what-if scenarios.
+    /// Accounts that don't exist are created from their override.
+    ///
+    /// Only the local estimator supports overrides, the RPC `simulateTransaction`
+    /// method has no way to take them.
+    pub account_overrides: HashMap<Pubkey, AccountOverride>,
+    /// Collects program logs in the format validators emit them. Disabled by default.
+    pub logs: LogCollection,
+}
+
+/// # RpcSimulationConfig
+///
+/// Configures the `simulate_*_msg_with_config` functions, which simulate on the RPC node.
+#[derive(Clone, Debug, Default)]
+pub struct RpcSimulationConfig {
+    /// Report the state of the message accounts after the simulation, with their diffs
+    /// and token balance changes.
+    ///
+    /// The state before the simulation is read with `getMultipleAccounts` requests
+    /// at the slot the simulation ran at or later, so this costs extra round trips.
+    pub account_diffs: bool,
+}
+
+/// # LogCollection
+///
+/// Selects whether the local estimator collects program logs, and how many.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LogCollection {
+    /// Logs aren't reported. They are still collected up to
+    /// [`LogCollection::VALIDATOR_BYTES_LIMIT`], to read the compute units of CPIs.
+    #[default]
+    Disabled,
+    /// Logs past this many bytes are replaced by a single `Log truncated` line.
+    BytesLimit(usize),
+    Unlimited,
+}
+
+impl LogCollection {
+    /// Byte limit validators apply to transaction logs by default.
+    pub const VALIDATOR_BYTES_LIMIT: usize = 10_000;
+
+    /// Log collector of the local execution.
+    pub(crate) fn log_collector(self) -> Rc<RefCell<LogCollector>> {
+        match self {
+            LogCollection::Disabled => {
+                LogCollector::new_ref_with_limit(Some(Self::VALIDATOR_BYTES_LIMIT))
+            }
+            LogCollection::BytesLimit(bytes_limit) => {
+                LogCollector::new_ref_with_limit(Some(bytes_limit))
+            }
+            LogCollection::Unlimited => LogCollector::new_ref_with_limit(None),
+        }
+    }
+}
+
+/// # AccountOverride
+///
+/// Fields replacing those of an account before local execution.
+/// Fields left to `None` keep their fetched value.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AccountOverride {
+    pub lamports: Option<u64>,
+    pub data: Option<Vec<u8>>,
+    pub owner: Option<Pubkey>,
+    pub executable: Option<bool>,
+}
+
+impl AccountOverride {
+    pub fn apply(&self, account: &mut AccountSharedData) {
+        if let Some(lamports) = self.lamports {
+            account.set_lamports(lamports);
+        }
+        if let Some(data) = &self.data {
+            account.set_data_from_slice(data);
+        }
+        if let Some(owner) = self.owner {
+            account.set_owner(owner);
+        }
+        if let Some(executable) = self.executable {
+            account.set_executable(executable);
+        }
+    }
+}
+
+/// # OptimizeConfig
+///
+/// Configures the `optimize_compute_units_*_with_config` functions.
+#[derive(Clone, Debug, Default)]
+pub struct OptimizeConfig {
+    /// Insert a compute unit limit even if the simulated transaction failed.
+    /// By default the simulation error is returned and the transaction is left untouched.
+    pub allow_failed_transaction: bool,
+    /// Margin added to the measured compute units.
+    pub margin: ComputeUnitMarginPolicy,
+}
+
+/// # ComputeUnitMarginPolicy
+///
+/// Margin added to the measured compute units before requesting them as the
+/// compute unit limit. The resulting limit is clamped to [`MAX_COMPUTE_UNIT_LIMIT`].
+#[derive(Clone)]
+pub enum ComputeUnitMarginPolicy {
+    /// A fixed number of compute units.
+    Fixed(u32),
+    /// A percentage of the measured compute units.
+    Percentage(u32),
+    /// A percentage of the measured compute units, bounded to `[min, max]` compute units.
+    /// `max` takes precedence if `min` exceeds it.
+    PercentageWithBounds { percentage: u32, min: u32, max: u32 },
+    /// Computes the limit from the measured compute units.
+    Custom(Arc<dyn Fn(u32) -> u32 + Send + Sync>),
+}
+
+impl ComputeUnitMarginPolicy {
+    /// Returns the compute unit limit to request for `units_consumed`.
+    pub fn compute_unit_limit(&self, units_consumed: u32) -> u32 {
+        let percentage_of = |percentage: u32| {
+            u32::try_from(u64::from(units_consumed) * u64::from(percentage) / 100)
+                .unwrap_or(u32::MAX)
+        };
+        let limit = match self {
+            ComputeUnitMarginPolicy::Fixed(margin) => units_consumed.saturating_add(*margin),
+            ComputeUnitMarginPolicy::Percentage(percentage) => {
+                units_consumed.saturating_add(percentage_of(*percentage))
+            }
+            ComputeUnitMarginPolicy::PercentageWithBounds {
+                percentage,
+                min,
+                max,
+            } => units_consumed.saturating_add(percentage_of(*percentage).max(*min).min(*max)),
+            ComputeUnitMarginPolicy::Custom(policy) => policy(units_consumed),
+        };
+        limit.min(MAX_COMPUTE_UNIT_LIMIT)
+    }
+}
+
+/// Adds 150 compute units to the measured compute units.
+impl Default for ComputeUnitMarginPolicy {
+    fn default() -> Self {
+        ComputeUnitMarginPolicy::Fixed(150)
+    }
+}
+
+impl Debug for ComputeUnitMarginPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ComputeUnitMarginPolicy::Fixed(margin) => f.debug_tuple("Fixed").field(margin).finish(),
+            ComputeUnitMarginPolicy::Percentage(percentage) => {
+                f.debug_tuple("Percentage").field(percentage).finish()
+            }
+            ComputeUnitMarginPolicy::PercentageWithBounds {
+                percentage,
+                min,
+                max,
+            } => f
+                .debug_struct("PercentageWithBounds")
+                .field("percentage", percentage)
+                .field("min", min)
+                .field("max", max)
+                .finish(),
+            ComputeUnitMarginPolicy::Custom(_) => f.write_str("Custom(..)"),
+        }
+    }
+}
+
+/// # PriorityFeePercentile
+///
+/// Percentile of the recent prioritization fees used as compute unit price.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PriorityFeePercentile {
+    #[default]
+    P50,
+    P75,
+    P90,
+    Max,
+}
+
+impl PriorityFeePercentile {
+    /// Returns the nearest-rank percentile of `fees`, or 0 if there are none.
+    pub fn select(&self, fees: &[u64]) -> u64 {
+        let mut fees = fees.to_vec();
+        fees.sort_unstable();
+        let percentage = match self {
+            PriorityFeePercentile::P50 => 50,
+            PriorityFeePercentile::P75 => 75,
+            PriorityFeePercentile::P90 => 90,
+            PriorityFeePercentile::Max => 100,
+        };
+        let rank = (fees.len() * percentage).div_ceil(100);
+        fees.get(rank.saturating_sub(1))
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+/// # PriorityFeeConfig
+///
+/// Configures the `optimize_priority_fee_*_with_config` functions.
+#[derive(Clone, Debug, Default)]
+pub struct PriorityFeeConfig {
+    /// Percentile of the fees paid in recent slots to lock the message's writable accounts.
+    pub percentile: PriorityFeePercentile,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn margin_policies() {
+        assert_eq!(
+            ComputeUnitMarginPolicy::Fixed(150).compute_unit_limit(1_000),
+            1_150
+        );
+        assert_eq!(
+            ComputeUnitMarginPolicy::default().compute_unit_limit(1_000),
+            1_150
+        );
+        let bounded = ComputeUnitMarginPolicy::PercentageWithBounds {
+            percentage: 10,
+            min: 500,
+            max: 5_000,
+        };
+        assert_eq!(bounded.compute_unit_limit(1_000), 1_500);
+        assert_eq!(bounded.compute_unit_limit(100_000), 105_000);
+        let inverted = ComputeUnitMarginPolicy::PercentageWithBounds {
+            percentage: 10,
+            min: 5_000,
+            max: 500,
+        };
+        assert_eq!(inverted.compute_unit_limit(1_000), 1_500);
+        let custom = ComputeUnitMarginPolicy::Custom(Arc::new(|units| units * 2));
+        assert_eq!(custom.compute_unit_limit(1_000_000), MAX_COMPUTE_UNIT_LIMIT);
+    }
+
+    #[test]
+    fn priority_fee_percentiles() {
+        let fees = [0, 40, 10, 30, 20, 0, 0, 50, 100, 1_000];
+        assert_eq!(PriorityFeePercentile::P50.select(&fees), 20);
+        assert_eq!(PriorityFeePercentile::P75.select(&fees), 50);
+        assert_eq!(PriorityFeePercentile::P90.select(&fees), 100);
+        assert_eq!(PriorityFeePercentile::Max.select(&fees), 1_000);
+        assert_eq!(PriorityFeePercentile::Max.select(&[]), 0);
+    }
+}
@@ -1,10 +1,32 @@
 use std::error::Error;
 use std::fmt::{Display, Formatter};
 
+use solana_instruction::error::InstructionError;
+use solana_pubkey::Pubkey;
+use solana_transaction_error::TransactionError;
+
 #[derive(Debug)]
 pub enum SolanaClientExtError {
     RpcError(String),
     ComputeUnitsError(String),
+    /// Instruction at the given index of the message failed.
+    InstructionError(u8, InstructionError),
+    /// The transaction failed before or outside of instruction execution.
+    TransactionError(TransactionError),
+    /// An account needed for local execution couldn't be fetched.
+    AccountFetchError(String),
+    /// The transaction couldn't be sanitized for local execution.
+    SanitizeError(TransactionError),
+    /// The program runtime environment couldn't be created.
+    RuntimeEnvironmentError(String),
+    /// An address lookup table couldn't be resolved.
+    AddressLookupTableError(String),
+    /// The message couldn't be compiled.
+    CompileError(String),
+    /// The signers don't cover these signers required by the message.
+    MissingSignerError(Vec<Pubkey>),
+    /// An account snapshot couldn't be read or written.
+    AccountSnapshotError(String),
 }
 
 impl Display for SolanaClientExtError {
@@ -14,8 +36,43 @@ impl Display for SolanaClientExtError {
             SolanaClientExtError::ComputeUnitsError(ref err) => {
                 write!(f, "Compute Units error: {}", err)
             }
+            SolanaClientExtError::InstructionError(index, ref err) => {
+                write!(f, "Instruction {} failed: {}", index, err)
+            }
+            SolanaClientExtError::TransactionError(ref err) => {
+                write!(f, "Transaction error: {}", err)
+            }
+            SolanaClientExtError::AccountFetchError(ref err) => {
+                write!(f, "Account fetch error: {}", err)
+            }
+            SolanaClientExtError::SanitizeError(ref err) => write!(f, "Sanitize error: {}", err),
+            SolanaClientExtError::RuntimeEnvironmentError(ref err) => {
+                write!(f, "Runtime environment error: {}", err)
+            }
+            SolanaClientExtError::AddressLookupTableError(ref err) => {
+                write!(f, "Address lookup table error: {}", err)
+            }
+            SolanaClientExtError::CompileError(ref err) => write!(f, "Compile error: {}", err),
+            SolanaClientExtError::MissingSignerError(ref pubkeys) => {
+                let pubkeys = pubkeys.iter().map(ToString::to_string).collect::<Vec<_>>();
+                write!(f, "Missing signers: {}", pubkeys.join(", "))
+            }
+            SolanaClientExtError::AccountSnapshotError(ref err) => {
+                write!(f, "Account snapshot error: {}", err)
+            }
         }
     }
 }
 
 impl Error for SolanaClientExtError {}
+
+impl From<TransactionError> for SolanaClientExtError {
+    fn from(err: TransactionError) -> Self {
+        match err {
+            TransactionError::InstructionError(index, err) => {
+                SolanaClientExtError::InstructionError(index, err)
+            }
+            err => SolanaClientExtError::TransactionError(err),
+        }
+    }
+}
@@ -0,0 +1,187 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, LazyLock, Mutex},
+};
+
+use agave_feature_set::{FeatureSet, FEATURE_NAMES};
+use solana_account::ReadableAccount;
+use solana_client::{nonblocking, rpc_client::RpcClient};
+use solana_clock::Epoch;
+use solana_pubkey::Pubkey;
+
+use crate::{
+    account_source::{AccountSource, AsyncAccountSource, AsyncRpcAccountSource, RpcAccountSource},
+    error::SolanaClientExtError,
+};
+
+/// Maximum number of accounts accepted by a single `getMultipleAccounts` request.
+pub(crate) const MAX_MULTIPLE_ACCOUNTS: usize = 100;
+
+/// Feature set read from a cluster, valid for the epoch it was read in.
+type EpochFeatureSet = (Epoch, Arc<FeatureSet>);
+
+/// Feature sets read from each cluster, keyed by RPC url.
+static CLUSTER_FEATURE_SETS: LazyLock<Mutex<HashMap<String, EpochFeatureSet>>> =
+    LazyLock::new(Default::default);
+
+/// # FeatureSetSource
+///
+/// Selects the features active during local execution.
+#[derive(Clone, Debug, Default)]
+pub enum FeatureSetSource {
+    /// Features activated on the cluster, read once per epoch.
+    #[default]
+    Cluster,
+    /// Every known feature active.
+    AllEnabled,
+    /// A feature set provided by the caller.
+    Custom(Arc<FeatureSet>),
+}
+
+impl FeatureSetSource {
+    /// Returns the feature set if it's known without reading feature accounts,
+    /// which is the case for every source but the cluster's feature set that wasn't
+    /// cached for `cluster_url` during `epoch`.
+    pub(crate) fn known(&self, cluster_url: Option<&str>, epoch: Epoch) -> Option<Arc<FeatureSet>> {
+        match self {
+            FeatureSetSource::Cluster => cluster_url.and_then(|url| cached_feature_set(url, epoch)),
+            FeatureSetSource::AllEnabled => Some(Arc::new(FeatureSet::all_enabled())),
+            FeatureSetSource::Custom(feature_set) => Some(feature_set.clone()),
+        }
+    }
+}
+
+/// Every known feature id, whose accounts make up the cluster's feature set.
+pub(crate) fn feature_ids() -> Vec<Pubkey> {
+    FEATURE_NAMES.keys().copied().collect()
+}
+
+/// Reads every known feature account from the cluster and returns the resulting feature set.
+pub fn fetch_cluster_feature_set(
+    rpc_client: &RpcClient,
+) -> Result<FeatureSet, SolanaClientExtError> {
+    let feature_ids = feature_ids();
+    let accounts = RpcAccountSource::new(rpc_client).get_accounts(&feature_ids)?;
+    Ok(feature_set_from_accounts(&feature_ids, accounts))
+}
+
+/// Same as [`fetch_cluster_feature_set`], with the nonblocking client.
+pub async fn fetch_cluster_feature_set_async(
+    rpc_client: &nonblocking::rpc_client::RpcClient,
+) -> Result<FeatureSet, SolanaClientExtError> {
+    let feature_ids = feature_ids();
+    let accounts = AsyncRpcAccountSource::new(rpc_client)
+        .get_accounts(&feature_ids)
+        .await?;
+    Ok(feature_set_from_accounts(&feature_ids, accounts))
+}
+
+/// Returns the feature set of the cluster, read once per epoch.
+pub(crate) fn cluster_feature_set(
+    rpc_client: &RpcClient,
+) -> Result<Arc<FeatureSet>, SolanaClientExtError> {
+    let epoch = rpc_client
+        .get_epoch_info()
+        .map_err(|err| SolanaClientExtError::RpcError(err.to_string()))?
+        .epoch;
+    if let Some(feature_set) = cached_feature_set(&rpc_client.url(), epoch) {
+        return Ok(feature_set);
+    }
+    let feature_set = Arc::new(fetch_cluster_feature_set(rpc_client)?);
+    cache_feature_set(rpc_client.url(), epoch, &feature_set);
+    Ok(feature_set)
+}
+
+/// Same as [`cluster_feature_set`], with the nonblocking client.
+pub(crate) async fn cluster_feature_set_async(
+    rpc_client: &nonblocking::rpc_client::RpcClient,
+) -> Result<Arc<FeatureSet>, SolanaClientExtError> {
+    let epoch = rpc_client
+        .get_epoch_info()
+        .await
+        .map_err(|err| SolanaClientExtError::RpcError(err.to_string()))?
+        .epoch;
+    if let Some(feature_set) = cached_feature_set(&rpc_client.url(), epoch) {
+        return Ok(feature_set);
+    }
+    let feature_set = Arc::new(fetch_cluster_feature_set_async(rpc_client).await?);
+    cache_feature_set(rpc_client.url(), epoch, &feature_set);
+    Ok(feature_set)
+}
+
+/// Feature set with the features of `feature_ids` whose account holds an activation slot.
+pub(crate) fn feature_set_from_accounts<T: ReadableAccount>(
+    feature_ids: &[Pubkey],
+    accounts: Vec<Option<T>>,
+) -> FeatureSet {
+    let mut feature_set = FeatureSet::default();
+    for (feature_id, account) in feature_ids.iter().zip(accounts) {
+        let activated_at = account
+            .and_then(|account| solana_feature_gate_interface::from_account(&account))
+            .and_then(|feature| feature.activated_at);
+        if let Some(slot) = activated_at {
+            feature_set.activate(feature_id, slot);
+        }
+    }
+    feature_set
+}
+
+/// Returns the feature set read from the cluster at `url`, if it was read during `epoch`.
+fn cached_feature_set(url: &str, epoch: Epoch) -> Option<Arc<FeatureSet>> {
+    CLUSTER_FEATURE_SETS
+        .lock()
+        .unwrap()
+        .get(url)
+        .filter(|(cached_epoch, _)| *cached_epoch == epoch)
+        .map(|(_, feature_set)| feature_set.clone())
+}
+
+pub(crate) fn cache_feature_set(url: String, epoch: Epoch, feature_set: &Arc<FeatureSet>) {
+    CLUSTER_FEATURE_SETS
+        .lock()
+        .unwrap()
+        .insert(url, (epoch, feature_set.clone()));
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_feature_gate_interface::Feature;
+
+    use super::*;
+
+    #[test]
+    fn decodes_feature_activations() {
+        let feature_ids = feature_ids()[..3].to_vec();
+        let accounts = vec![
+            Some(solana_feature_gate_interface::create_account(
+                &Feature {
+                    activated_at: Some(42),
+                },
+                1,
+            )),
+            Some(solana_feature_gate_interface::create_account(
+                &Feature { activated_at: None },
+                1,
+            )),
+            None,
+        ];
+
+        let feature_set = feature_set_from_accounts(&feature_ids, accounts);
+        assert_eq!(feature_set.activated_slot(&feature_ids[0]), Some(42));
+        assert!(!feature_set.is_active(&feature_ids[1]));
+        assert!(!feature_set.is_active(&feature_ids[2]));
+    }
+
+    #[test]
+    fn caches_cluster_feature_set_per_epoch() {
+        let url = "http://feature-set-cache.test";
+        let feature_set = Arc::new(FeatureSet::all_enabled());
+        cache_feature_set(url.to_string(), 5, &feature_set);
+
+        let source = FeatureSetSource::Cluster;
+        let cached = source.known(Some(url), 5).unwrap();
+        assert!(Arc::ptr_eq(&cached, &feature_set));
+        assert!(source.known(Some(url), 6).is_none());
+        assert!(source.known(None, 5).is_none());
+    }
+}
@@ -0,0 +1,171 @@
+use std::collections::HashSet;
+
+use agave_feature_set::FeatureSet;
+use solana_compute_budget_instruction::instructions_processor::process_compute_budget_instructions;
+use solana_fee_structure::FeeStructure;
+use solana_message::{Message, SanitizedMessage};
+use solana_svm_transaction::svm_message::SVMMessage;
+use solana_transaction_error::TransactionError;
+
+use crate::error::SolanaClientExtError;
+
+const MICRO_LAMPORTS_PER_LAMPORT: u128 = 1_000_000;
+
+/// # FeeEstimate
+///
+/// Fee charged for a message, in lamports.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FeeEstimate {
+    /// Transaction signatures and Ed25519, Secp256k1 and Secp256r1 precompile signatures.
+    pub num_signatures: u64,
+    /// Fee paid for the signatures.
+    pub signature_fee: u64,
+    /// Compute unit limit times the compute unit price of the message.
+    pub prioritization_fee: u64,
+    /// Signature fee plus prioritization fee.
+    pub total_fee: u64,
+    /// Fee returned by the `getFeeForMessage` RPC method.
+    pub cluster_fee: u64,
+}
+
+impl FeeEstimate {
+    /// Returns true if the fee computed locally is the one the cluster charges.
+    pub fn matches_cluster(&self) -> bool {
+        self.total_fee == self.cluster_fee
+    }
+}
+
+/// Computes the fee of `message` the way a validator with `feature_set` active does.
+///
+/// Without a `SetComputeUnitLimit` instruction, builtin and precompile instructions are
+/// allocated fewer compute units than other instructions. Builtins migrated to sBPF by
+/// a feature of `feature_set` count as other instructions.
+pub(crate) fn estimate_fee(
+    message: &Message,
+    cluster_fee: u64,
+    feature_set: &FeatureSet,
+) -> Result<FeeEstimate, SolanaClientExtError> {
+    let message = SanitizedMessage::try_from_legacy_message(message.clone(), &HashSet::new())
+        .map_err(|_| SolanaClientExtError::SanitizeError(TransactionError::SanitizeFailure))?;
+    Ok(FeeEstimate {
+        cluster_fee,
+        ..sanitized_message_fee(&message, feature_set)?
+    })
+}
+
+/// Same as [`estimate_fee`] for a sanitized message, leaving the cluster fee at 0.
+pub(crate) fn sanitized_message_fee(
+    message: &SanitizedMessage,
+    feature_set: &FeatureSet,
+) -> Result<FeeEstimate, SolanaClientExtError> {
+    let num_signatures = message
+        .num_transaction_signatures()
+        .saturating_add(message.num_ed25519_signatures())
+        .saturating_add(message.num_secp256k1_signatures())
+        .saturating_add(message.num_secp256r1_signatures());
+    let signature_fee =
+        num_signatures.saturating_mul(FeeStructure::default().lamports_per_signature);
+
+    let limits = process_compute_budget_instructions(
+        SVMMessage::program_instructions_iter(message),
+        feature_set,
+    )
+    .map_err(SolanaClientExtError::TransactionError)?;
+    let prioritization_fee = (u128::from(limits.compute_unit_limit)
+        * u128::from(limits.compute_unit_price))
+    .div_ceil(MICRO_LAMPORTS_PER_LAMPORT);
+    let prioritization_fee = u64::try_from(prioritization_fee).unwrap_or(u64::MAX);
+
+    Ok(FeeEstimate {
+        num_signatures,
+        signature_fee,
+        prioritization_fee,
+        total_fee: signature_fee.saturating_add(prioritization_fee),
+        cluster_fee: 0,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_compute_budget_interface::ComputeBudgetInstruction;
+    use solana_instruction::Instruction;
+    use solana_pubkey::Pubkey;
+    use solana_sdk_ids::{ed25519_program, stake};
+
+    use super::*;
+
+    #[test]
+    fn prioritization_fee() {
+        let payer = Pubkey::new_unique();
+        let instruction = Instruction::new_with_bytes(Pubkey::new_unique(), &[], vec![]);
+        let message = Message::new(
+            &[
+                ComputeBudgetInstruction::set_compute_unit_limit(300_000),
+                ComputeBudgetInstruction::set_compute_unit_price(10_001),
+                instruction.clone(),
+            ],
+            Some(&payer),
+        );
+
+        let fee = estimate_fee(&message, 8_001, &FeatureSet::default()).unwrap();
+        assert_eq!(fee.signature_fee, 5_000);
+        assert_eq!(fee.prioritization_fee, 3_001);
+        assert!(fee.matches_cluster());
+
+        let message = Message::new(
+            &[
+                ComputeBudgetInstruction::set_compute_unit_price(1_000_000),
+                instruction,
+            ],
+            Some(&payer),
+        );
+        assert_eq!(
+            estimate_fee(&message, 0, &FeatureSet::default())
+                .unwrap()
+                .prioritization_fee,
+            203_000
+        );
+    }
+
+    #[test]
+    fn precompile_allocation() {
+        let payer = Pubkey::new_unique();
+        let message = Message::new(
+            &[
+                ComputeBudgetInstruction::set_compute_unit_price(1_000_000),
+                Instruction::new_with_bytes(ed25519_program::id(), &[1], vec![]),
+            ],
+            Some(&payer),
+        );
+
+        let fee = estimate_fee(&message, 0, &FeatureSet::default()).unwrap();
+        assert_eq!(fee.num_signatures, 2);
+        assert_eq!(fee.signature_fee, 10_000);
+        assert_eq!(fee.prioritization_fee, 6_000);
+    }
+
+    #[test]
+    fn migrated_builtin_allocation() {
+        let payer = Pubkey::new_unique();
+        let message = Message::new(
+            &[
+                ComputeBudgetInstruction::set_compute_unit_price(1_000_000),
+                Instruction::new_with_bytes(stake::id(), &[], vec![]),
+            ],
+            Some(&payer),
+        );
+        let prioritization_fee = |feature_set: &FeatureSet| {
+            estimate_fee(&message, 0, feature_set)
+                .unwrap()
+                .prioritization_fee
+        };
+
+        assert_eq!(prioritization_fee(&FeatureSet::default()), 6_000);
+        let mut feature_set = FeatureSet::default();
+        feature_set.activate(
+            &agave_feature_set::migrate_stake_program_to_core_bpf::id(),
+            0,
+        );
+        assert_eq!(prioritization_fee(&feature_set), 203_000);
+    }
+}
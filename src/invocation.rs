@@ -0,0 +1,444 @@
+use serde::{Serialize, Serializer};
+use solana_message::compiled_instruction::CompiledInstruction;
+use solana_pubkey::Pubkey;
+use solana_transaction_error::{TransactionError, TransactionResult};
+use solana_transaction_status_client_types::{UiInnerInstructions, UiInstruction};
+
+use crate::report::InstructionComputeUnits;
+
+/// # InvocationNode
+///
+/// An instruction executed by a simulated transaction and the instructions it invoked through CPI.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InvocationNode {
+    #[serde(serialize_with = "serialize_pubkey")]
+    pub program_id: Pubkey,
+    /// Instruction data, serialized as base58 like RPC instructions.
+    #[serde(serialize_with = "serialize_base58")]
+    pub data: Vec<u8>,
+    #[serde(serialize_with = "serialize_pubkeys")]
+    pub accounts: Vec<Pubkey>,
+    /// 1 for top level instructions, incremented by every CPI.
+    pub stack_height: usize,
+    /// Compute units consumed by the instruction, including its CPIs, if known.
+    pub units_consumed: Option<u64>,
+    /// Log lines emitted by the program itself, without the logs of its CPIs.
+    pub logs: Vec<String>,
+    /// Error the instruction failed with, `None` if it succeeded.
+    pub error: Option<String>,
+    /// Instructions invoked through CPI, in invocation order.
+    pub inner: Vec<InvocationNode>,
+}
+
+impl InvocationNode {
+    pub(crate) fn new(
+        program_id: Pubkey,
+        data: Vec<u8>,
+        accounts: Vec<Pubkey>,
+        stack_height: usize,
+    ) -> Self {
+        Self {
+            program_id,
+            data,
+            accounts,
+            stack_height,
+            units_consumed: None,
+            logs: Vec::new(),
+            error: None,
+            inner: Vec::new(),
+        }
+    }
+}
+
+/// An invocation as reported by the program logs.
+pub(crate) struct LoggedInvocation {
+    pub(crate) program_id: Pubkey,
+    pub(crate) stack_height: usize,
+    pub(crate) units_consumed: Option<u64>,
+    logs: Vec<String>,
+    error: Option<String>,
+}
+
+/// Splits program logs into the invocations which emitted them, in invocation order.
+///
+/// Relies on the `invoke [N]`, `consumed X of Y compute units`, `success` and `failed` lines
+/// the program runtime emits around every instruction.
+pub(crate) fn logged_invocations(logs: &[String]) -> Vec<LoggedInvocation> {
+    let mut invocations: Vec<LoggedInvocation> = Vec::new();
+    let mut invoke_stack: Vec<usize> = Vec::new();
+    for log in logs {
+        let program_log = log
+            .strip_prefix("Program ")
+            .and_then(|log| log.split_once(' '))
+            .and_then(|(program_id, rest)| Some((program_id.parse::<Pubkey>().ok()?, rest)));
+
+        if let Some((program_id, rest)) = program_log {
+            if let Some(stack_height) = rest
+                .strip_prefix("invoke [")
+                .and_then(|rest| rest.strip_suffix(']'))
+                .and_then(|stack_height| stack_height.parse::<usize>().ok())
+            {
+                invoke_stack.truncate(stack_height.saturating_sub(1));
+                invoke_stack.push(invocations.len());
+                invocations.push(LoggedInvocation {
+                    program_id,
+                    stack_height,
+                    units_consumed: None,
+                    logs: Vec::new(),
+                    error: None,
+                });
+                continue;
+            }
+
+            let Some(invocation) = invoke_stack
+                .last()
+                .and_then(|index| invocations.get_mut(*index))
+                .filter(|invocation| invocation.program_id == program_id)
+            else {
+                continue;
+            };
+            if let Some(units_consumed) = rest
+                .strip_prefix("consumed ")
+                .and_then(|rest| rest.split_once(' '))
+                .and_then(|(units_consumed, _)| units_consumed.parse().ok())
+            {
+                invocation.units_consumed = Some(units_consumed);
+                invocation.logs.push(log.clone());
+            } else if rest == "success" {
+                invoke_stack.pop();
+            } else if let Some(error) = rest.strip_prefix("failed: ") {
+                invocation.error = Some(error.to_string());
+                invoke_stack.pop();
+            } else {
+                invocation.logs.push(log.clone());
+            }
+        } else if let Some(invocation) = invoke_stack
+            .last()
+            .and_then(|index| invocations.get_mut(*index))
+        {
+            invocation.logs.push(log.clone());
+        }
+    }
+    invocations
+}
+
+/// Compute units of flat `invocations`, listed in invocation order.
+pub(crate) fn instruction_compute_units(
+    invocations: &[InvocationNode],
+) -> Vec<InstructionComputeUnits> {
+    let mut instruction_index = 0;
+    invocations
+        .iter()
+        .enumerate()
+        .map(|(index, invocation)| {
+            if invocation.stack_height == 1 && index > 0 {
+                instruction_index += 1;
+            }
+            InstructionComputeUnits {
+                instruction_index,
+                program_id: invocation.program_id,
+                stack_height: invocation.stack_height,
+                units_consumed: invocation.units_consumed,
+            }
+        })
+        .collect()
+}
+
+/// Flat invocations of an RPC simulation, rebuilt from the top level `instructions`
+/// of the message and the `inner_instructions` returned by the node.
+///
+/// Only the top level instructions which were executed are listed.
+pub(crate) fn rpc_invocations(
+    instructions: &[CompiledInstruction],
+    account_keys: &[Pubkey],
+    inner_instructions: &[UiInnerInstructions],
+    result: &TransactionResult<()>,
+) -> Vec<InvocationNode> {
+    let num_executed = match result {
+        Ok(()) => instructions.len(),
+        Err(TransactionError::InstructionError(index, _)) => usize::from(*index) + 1,
+        Err(_) => 0,
+    };
+    let key = |index: u8| {
+        account_keys
+            .get(usize::from(index))
+            .copied()
+            .unwrap_or_default()
+    };
+
+    let mut invocations = Vec::new();
+    for (index, instruction) in instructions.iter().take(num_executed).enumerate() {
+        invocations.push(InvocationNode::new(
+            key(instruction.program_id_index),
+            instruction.data.clone(),
+            instruction.accounts.iter().copied().map(key).collect(),
+            1,
+        ));
+        let inner = inner_instructions
+            .iter()
+            .filter(|inner| usize::from(inner.index) == index)
+            .flat_map(|inner| &inner.instructions);
+        for instruction in inner {
+            let UiInstruction::Compiled(instruction) = instruction else {
+                continue;
+            };
+            invocations.push(InvocationNode::new(
+                key(instruction.program_id_index),
+                bs58::decode(&instruction.data)
+                    .into_vec()
+                    .unwrap_or_default(),
+                instruction.accounts.iter().copied().map(key).collect(),
+                instruction
+                    .stack_height
+                    .and_then(|stack_height| usize::try_from(stack_height).ok())
+                    .unwrap_or(2),
+            ));
+        }
+    }
+    invocations
+}
+
+/// Fills flat `invocations` with what `logs` and `result` tell about them.
+///
+/// Precompiles are verified without being invoked, so they don't log and are skipped.
+/// Without logs, only the failing top level instruction is known to have failed.
+pub(crate) fn annotate_invocations(
+    invocations: &mut [InvocationNode],
+    logs: &[String],
+    result: &TransactionResult<()>,
+) {
+    let logged_nodes = invocations
+        .iter_mut()
+        .filter(|invocation| !agave_precompiles::is_precompile(&invocation.program_id, |_| true));
+    for (invocation, logged) in logged_nodes.zip(logged_invocations(logs)) {
+        if invocation.program_id != logged.program_id
+            || invocation.stack_height != logged.stack_height
+        {
+            break;
+        }
+        invocation.units_consumed = invocation.units_consumed.or(logged.units_consumed);
+        invocation.logs = logged.logs;
+        invocation.error = logged.error;
+    }
+    if let Err(TransactionError::InstructionError(index, err)) = result {
+        if let Some(invocation) = invocations
+            .iter_mut()
+            .filter(|invocation| invocation.stack_height == 1)
+            .nth(usize::from(*index))
+        {
+            invocation.error.get_or_insert_with(|| err.to_string());
+        }
+    }
+}
+
+/// Nests every CPI of flat `invocations` under the instruction which invoked it.
+pub(crate) fn invocation_tree(invocations: Vec<InvocationNode>) -> Vec<InvocationNode> {
+    let mut roots: Vec<InvocationNode> = Vec::new();
+    let mut stack: Vec<InvocationNode> = Vec::new();
+    for invocation in invocations {
+        while stack
+            .last()
+            .is_some_and(|parent| parent.stack_height >= invocation.stack_height)
+        {
+            close_invocation(&mut stack, &mut roots);
+        }
+        stack.push(invocation);
+    }
+    while !stack.is_empty() {
+        close_invocation(&mut stack, &mut roots);
+    }
+    roots
+}
+
+/// Moves the innermost open invocation under its parent, or to the roots.
+fn close_invocation(stack: &mut Vec<InvocationNode>, roots: &mut Vec<InvocationNode>) {
+    let Some(invocation) = stack.pop() else {
+        return;
+    };
+    match stack.last_mut() {
+        Some(parent) => parent.inner.push(invocation),
+        None => roots.push(invocation),
+    }
+}
+
+fn serialize_pubkey<S: Serializer>(pubkey: &Pubkey, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(pubkey)
+}
+
+fn serialize_pubkeys<S: Serializer>(pubkeys: &[Pubkey], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(pubkeys.iter().map(ToString::to_string))
+}
+
+fn serialize_base58<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&bs58::encode(data).into_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_instruction::error::InstructionError;
+
+    use super::*;
+
+    #[test]
+    fn nests_cpis_from_logs() {
+        let program = Pubkey::new_unique();
+        let system_program = solana_sdk_ids::system_program::id();
+        let logs = [
+            format!("Program {program} invoke [1]"),
+            "Program log: Instruction: Transfer".to_string(),
+            format!("Program {system_program} invoke [2]"),
+            format!("Program {system_program} success"),
+            format!("Program {program} consumed 4500 of 200000 compute units"),
+            format!("Program {program} failed: custom program error: 0x1"),
+        ];
+        let mut invocations = vec![
+            InvocationNode::new(program, vec![1], vec![system_program], 1),
+            InvocationNode::new(system_program, vec![2], vec![], 2),
+        ];
+        let result = Err(TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(1),
+        ));
+
+        annotate_invocations(&mut invocations, &logs, &result);
+        let tree = invocation_tree(invocations);
+        assert_eq!(tree.len(), 1);
+        assert_eq!(tree[0].units_consumed, Some(4500));
+        assert_eq!(tree[0].logs, [logs[1].clone(), logs[4].clone()].to_vec());
+        assert_eq!(tree[0].error.as_deref(), Some("custom program error: 0x1"));
+        assert_eq!(tree[0].inner.len(), 1);
+        assert_eq!(tree[0].inner[0].program_id, system_program);
+        assert_eq!(tree[0].inner[0].error, None);
+    }
+
+    #[test]
+    fn skips_precompiles_without_logs() {
+        let program = Pubkey::new_unique();
+        let ed25519_program = solana_sdk_ids::ed25519_program::id();
+        let logs = [
+            format!("Program {program} invoke [1]"),
+            format!("Program {program} consumed 1200 of 200000 compute units"),
+            format!("Program {program} success"),
+        ];
+
+        let mut invocations = vec![
+            InvocationNode::new(ed25519_program, vec![0, 0], vec![], 1),
+            InvocationNode::new(program, vec![1], vec![], 1),
+        ];
+        annotate_invocations(&mut invocations, &logs, &Ok(()));
+
+        assert_eq!(
+            instruction_compute_units(&invocations),
+            vec![
+                InstructionComputeUnits {
+                    instruction_index: 0,
+                    program_id: ed25519_program,
+                    stack_height: 1,
+                    units_consumed: None,
+                },
+                InstructionComputeUnits {
+                    instruction_index: 1,
+                    program_id: program,
+                    stack_height: 1,
+                    units_consumed: Some(1200),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn serializes_tree_after_leading_precompile() {
+        let program = Pubkey::new_unique();
+        let system_program = solana_sdk_ids::system_program::id();
+        let ed25519_program = solana_sdk_ids::ed25519_program::id();
+        let logs = [
+            format!("Program {program} invoke [1]"),
+            format!("Program {system_program} invoke [2]"),
+            format!("Program {system_program} success"),
+            format!("Program {program} consumed 4500 of 200000 compute units"),
+            format!("Program {program} success"),
+        ];
+        let mut invocations = vec![
+            InvocationNode::new(ed25519_program, vec![0, 0], vec![], 1),
+            InvocationNode::new(program, vec![1], vec![system_program], 1),
+            InvocationNode::new(system_program, vec![2], vec![], 2),
+        ];
+
+        annotate_invocations(&mut invocations, &logs, &Ok(()));
+        let tree = serde_json::to_value(invocation_tree(invocations)).unwrap();
+        assert_eq!(
+            tree,
+            serde_json::json!([
+                {
+                    "programId": ed25519_program.to_string(),
+                    "data": bs58::encode([0, 0]).into_string(),
+                    "accounts": [],
+                    "stackHeight": 1,
+                    "unitsConsumed": null,
+                    "logs": [],
+                    "error": null,
+                    "inner": [],
+                },
+                {
+                    "programId": program.to_string(),
+                    "data": bs58::encode([1]).into_string(),
+                    "accounts": [system_program.to_string()],
+                    "stackHeight": 1,
+                    "unitsConsumed": 4500,
+                    "logs": [logs[3]],
+                    "error": null,
+                    "inner": [{
+                        "programId": system_program.to_string(),
+                        "data": bs58::encode([2]).into_string(),
+                        "accounts": [],
+                        "stackHeight": 2,
+                        "unitsConsumed": null,
+                        "logs": [],
+                        "error": null,
+                        "inner": [],
+                    }],
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn instruction_compute_units_from_logs() {
+        let program = Pubkey::new_unique();
+        let system_program = solana_sdk_ids::system_program::id();
+        let logs = [
+            format!("Program {system_program} invoke [1]"),
+            format!("Program {system_program} success"),
+            format!("Program {program} invoke [1]"),
+            "Program log: Instruction: Transfer".to_string(),
+            format!("Program {system_program} invoke [2]"),
+            format!("Program {system_program} success"),
+            format!("Program {program} consumed 4500 of 199850 compute units"),
+            format!("Program {program} success"),
+        ];
+
+        let mut invocations = vec![
+            InvocationNode::new(system_program, vec![2], vec![], 1),
+            InvocationNode::new(program, vec![1], vec![system_program], 1),
+            InvocationNode::new(system_program, vec![2], vec![], 2),
+        ];
+        annotate_invocations(&mut invocations, &logs, &Ok(()));
+
+        let instruction =
+            |instruction_index, program_id, stack_height, units_consumed| InstructionComputeUnits {
+                instruction_index,
+                program_id,
+                stack_height,
+                units_consumed,
+            };
+        assert_eq!(
+            instruction_compute_units(&invocations),
+            vec![
+                instruction(0, system_program, 1, None),
+                instruction(1, program, 1, Some(4500)),
+                instruction(1, system_program, 2, None),
+            ]
+        );
+    }
+}
@@ -1,180 +1,334 @@
-use std::{collections::HashSet, sync::Arc};
-
-use error::SolanaClientExtError;
-use solana_account::AccountSharedData;
-use solana_client::{rpc_client, rpc_config::RpcSimulateTransactionConfig};
-use solana_clock::{Epoch, Slot};
-use solana_compute_budget::compute_budget::{self, ComputeBudget};
+use solana_clock::Slot;
 use solana_compute_budget_interface::ComputeBudgetInstruction;
-use agave_feature_set::FeatureSet;
-use solana_fee_structure::FeeStructure;
-use solana_hash::Hash;
-use solana_message::Message;
-use solana_program_runtime::sysvar_cache;
+use solana_message::{Message, VersionedMessage};
 use solana_pubkey::Pubkey;
-use solana_rent::Rent;
 use solana_signer::signers::Signers;
-use solana_transaction_context::TransactionContext;
-use solana_bpf_loader_program::syscalls::create_program_runtime_environment_v1;
-// use solana_sdk::{
-//     account::AccountSharedData,
-//     compute_budget::ComputeBudgetInstruction,
-//     message::Message,
-//     signers::Signers,
-//     transaction::{SanitizedTransaction, Transaction},
-//     transaction_context::TransactionContext,
-// };
-
-use {
-    solana_program_runtime::{
-        invoke_context::{self, EnvironmentConfig, InvokeContext},
-        loaded_programs::{ProgramCacheForTxBatch, ProgramRuntimeEnvironments},
-    },
-    solana_svm_transaction::svm_message::SVMMessage,
-    solana_timings::{ExecuteDetailsTimings, ExecuteTimings},
-
-};
-use solana_svm::message_processor; // MessageProcessor::process_message;
-use solana_transaction::{sanitized::SanitizedTransaction, Transaction};
+use solana_transaction::{versioned::VersionedTransaction, Transaction};
 
+pub mod account_source;
+pub mod builtins;
+mod compute_budget;
+pub mod config;
 mod error;
+pub mod feature_set;
+pub mod fee;
+mod invocation;
+mod local;
+mod lookup_tables;
+mod message_processor;
+pub mod nonblocking;
+pub mod optimizer;
+mod priority_fee;
+mod programs;
+pub mod report;
+pub mod sysvars;
+pub mod token;
+
+pub use account_source::{
+    estimate_compute_units_unsigned_tx_offline, simulate_unsigned_tx_offline, AccountSnapshot,
+    AccountSource, AsyncAccountSource, AsyncRpcAccountSource, RpcAccountSource,
+};
+pub use config::{
+    AccountOverride, ComputeUnitMarginPolicy, LocalSimulationConfig, LogCollection, OptimizeConfig,
+    PriorityFeeConfig, PriorityFeePercentile, RpcSimulationConfig,
+};
+pub use error::SolanaClientExtError;
+pub use feature_set::FeatureSetSource;
+pub use fee::FeeEstimate;
+pub use invocation::InvocationNode;
+pub use nonblocking::AsyncRpcClientExt;
+pub use optimizer::{OptimizationSummary, TransactionOptimizer};
+pub use report::{
+    AccountDiff, AccountStatus, ComputeUnitLimit, InstructionComputeUnits, SimulationReport,
+};
+pub use sysvars::Sysvars;
+pub use token::TokenBalanceChange;
 
 /// # RpcClientExt
 ///
 /// `RpcClientExt` is an extension trait for the rust solana client.
 /// This crate provides extensions for the Solana Rust client, focusing on compute unit estimation and optimization.
 pub trait RpcClientExt {
+    fn simulate_unsigned_tx<'a, I: Signers + ?Sized>(
+        &self,
+        unsigned_transaction: &Transaction,
+        signers: &'a I,
+    ) -> Result<SimulationReport, Box<dyn std::error::Error + 'static>>;
+
+    fn simulate_unsigned_tx_with_config<'a, I: Signers + ?Sized>(
+        &self,
+        unsigned_transaction: &Transaction,
+        signers: &'a I,
+        config: &LocalSimulationConfig,
+    ) -> Result<SimulationReport, Box<dyn std::error::Error + 'static>>;
+
+    fn simulate_msg<'a, I: Signers + ?Sized>(
+        &self,
+        msg: &Message,
+        signers: &'a I,
+    ) -> Result<SimulationReport, Box<dyn std::error::Error + 'static>>;
+
+    fn simulate_msg_with_config<'a, I: Signers + ?Sized>(
+        &self,
+        msg: &Message,
+        signers: &'a I,
+        config: &RpcSimulationConfig,
+    ) -> Result<SimulationReport, Box<dyn std::error::Error + 'static>>;
+
     fn estimate_compute_units_unsigned_tx<'a, I: Signers + ?Sized>(
         &self,
         unsigned_transaction: &Transaction,
         signers: &'a I,
     ) -> Result<u64, Box<dyn std::error::Error + 'static>>;
 
+    fn estimate_compute_units_unsigned_tx_with_config<'a, I: Signers + ?Sized>(
+        &self,
+        unsigned_transaction: &Transaction,
+        signers: &'a I,
+        config: &LocalSimulationConfig,
+    ) -> Result<u64, Box<dyn std::error::Error + 'static>>;
+
     fn estimate_compute_units_msg<'a, I: Signers + ?Sized>(
         &self,
         msg: &Message,
         signers: &'a I,
     ) -> Result<u64, Box<dyn std::error::Error + 'static>>;
 
+    fn simulate_unsigned_msg(
+        &self,
+        msg: &Message,
+    ) -> Result<SimulationReport, Box<dyn std::error::Error + 'static>>;
+
+    fn simulate_unsigned_msg_with_config(
+        &self,
+        msg: &Message,
+        config: &RpcSimulationConfig,
+    ) -> Result<SimulationReport, Box<dyn std::error::Error + 'static>>;
+
+    fn estimate_compute_units_unsigned_msg(
+        &self,
+        msg: &Message,
+    ) -> Result<u64, Box<dyn std::error::Error + 'static>>;
+
+    fn estimate_fee_msg(
+        &self,
+        msg: &Message,
+    ) -> Result<FeeEstimate, Box<dyn std::error::Error + 'static>>;
+
     fn optimize_compute_units_unsigned_tx<'a, I: Signers + ?Sized>(
         &self,
         unsigned_transaction: &mut Transaction,
         signers: &'a I,
     ) -> Result<u32, Box<dyn std::error::Error + 'static>>;
 
+    fn optimize_compute_units_unsigned_tx_with_config<'a, I: Signers + ?Sized>(
+        &self,
+        unsigned_transaction: &mut Transaction,
+        signers: &'a I,
+        config: &OptimizeConfig,
+    ) -> Result<ComputeUnitLimit, Box<dyn std::error::Error + 'static>>;
+
     fn optimize_compute_units_msg<'a, I: Signers + ?Sized>(
         &self,
         message: &mut Message,
         signers: &'a I,
     ) -> Result<u32, Box<dyn std::error::Error + 'static>>;
-}
 
-impl RpcClientExt for solana_client::rpc_client::RpcClient {
-    fn estimate_compute_units_unsigned_tx<'a, I: Signers + ?Sized>(
+    fn optimize_compute_units_msg_with_config<'a, I: Signers + ?Sized>(
         &self,
-        transaction: &Transaction,
-        _signers: &'a I,
-    ) -> Result<u64, Box<dyn std::error::Error + 'static>> {
-        // GET SVM MESSAGE
-        let sanitized = SanitizedTransaction::try_from_legacy_transaction(
-            Transaction::from(transaction.clone()),
-            &HashSet::new(),
-        );
+        message: &mut Message,
+        signers: &'a I,
+        config: &OptimizeConfig,
+    ) -> Result<ComputeUnitLimit, Box<dyn std::error::Error + 'static>>;
+    fn optimize_priority_fee_unsigned_tx(
+        &self,
+        unsigned_transaction: &mut Transaction,
+    ) -> Result<u64, Box<dyn std::error::Error + 'static>>;
 
+    fn optimize_priority_fee_unsigned_tx_with_config(
+        &self,
+        unsigned_transaction: &mut Transaction,
+        config: &PriorityFeeConfig,
+    ) -> Result<u64, Box<dyn std::error::Error + 'static>>;
 
-        let compute_budget = ComputeBudget::default();
-        let feature_set = FeatureSet::all_enabled();
-        let fee_structure = FeeStructure::default();
-        let lamports_per_signature = fee_structure.lamports_per_signature;
+    fn optimize_priority_fee_msg(
+        &self,
+        message: &mut Message,
+    ) -> Result<u64, Box<dyn std::error::Error + 'static>>;
 
-        //Get pubkeys from Tx
-        let accounts = &transaction.message.account_keys;
-        //call PRC client to get account shared data
-        let mut accounts_data = vec![];
-        for key in accounts {
-            let data: AccountSharedData = self.get_account(&key).unwrap().into();
-            accounts_data.push((*key, data));
-        }
+    fn optimize_priority_fee_msg_with_config(
+        &self,
+        message: &mut Message,
+        config: &PriorityFeeConfig,
+    ) -> Result<u64, Box<dyn std::error::Error + 'static>>;
 
-        // Get Invoke context
-        let mut transaction_context = TransactionContext::new(accounts_data, Rent::default(), 0, 0);
+    fn simulate_versioned_tx<'a, I: Signers + ?Sized>(
+        &self,
+        transaction: &VersionedTransaction,
+        signers: &'a I,
+    ) -> Result<SimulationReport, Box<dyn std::error::Error + 'static>>;
 
-        let runtime_env = Arc::new(
-            create_program_runtime_environment_v1(&feature_set, &compute_budget, false, false)
-                .unwrap(),
-        );
-        let sysvar_c = sysvar_cache::SysvarCache::default();
+    fn simulate_versioned_tx_with_config<'a, I: Signers + ?Sized>(
+        &self,
+        transaction: &VersionedTransaction,
+        signers: &'a I,
+        config: &LocalSimulationConfig,
+    ) -> Result<SimulationReport, Box<dyn std::error::Error + 'static>>;
 
-        let closure = |pubkey: &Pubkey| {
-            // get epoch vote account stake
-            0 // Return 0 if None
-        };
+    fn simulate_versioned_msg<'a, I: Signers + ?Sized>(
+        &self,
+        msg: &VersionedMessage,
+        signers: &'a I,
+    ) -> Result<SimulationReport, Box<dyn std::error::Error + 'static>>;
 
-        let env_config = EnvironmentConfig::new(
-            Hash::default(),
-            lamports_per_signature,
-            300_000_000,
-            &closure,
-            Arc::new(feature_set.clone()),
-            &sysvar_c,
-        );
+    fn simulate_versioned_msg_with_config<'a, I: Signers + ?Sized>(
+        &self,
+        msg: &VersionedMessage,
+        signers: &'a I,
+        config: &RpcSimulationConfig,
+    ) -> Result<SimulationReport, Box<dyn std::error::Error + 'static>>;
 
-        //Get prog_cache
-        let mut prog_cache = ProgramCacheForTxBatch::new(
-            Slot::default(), //Slot
-            
-            //enviorements
-            ProgramRuntimeEnvironments::default(),
-            None,             //Option<ProgramRuntimeEnvironments>
-            Epoch::default(), //Epoch
-        );
+    fn estimate_compute_units_versioned_tx<'a, I: Signers + ?Sized>(
+        &self,
+        transaction: &VersionedTransaction,
+        signers: &'a I,
+    ) -> Result<u64, Box<dyn std::error::Error + 'static>>;
 
-        let mut invoke_context = InvokeContext::new(
-            &mut transaction_context,             //&'a mut TransactionContext,,
-            &mut prog_cache,                      //&'a mut ProgramCacheForTxBatch,
-            env_config,                                  //EnvironmentConfig<'a>,
-            None,                                 //Option<Rc<RefCell<LogCollector>>>,
-            compute_budget.to_owned(),            //execution_cost: SVMTransactionExecutionCost,
-            // SVMTransactionExecutionCost::Default, //SVMTransactionExecutionCost ??
-        );
+    fn estimate_compute_units_versioned_tx_with_config<'a, I: Signers + ?Sized>(
+        &self,
+        transaction: &VersionedTransaction,
+        signers: &'a I,
+        config: &LocalSimulationConfig,
+    ) -> Result<u64, Box<dyn std::error::Error + 'static>>;
+
+    fn estimate_compute_units_versioned_msg<'a, I: Signers + ?Sized>(
+        &self,
+        msg: &VersionedMessage,
+        signers: &'a I,
+    ) -> Result<u64, Box<dyn std::error::Error + 'static>>;
 
-        // Get Timmings
-        let mut timings = ExecuteTimings::default();
+    fn optimize_compute_units_versioned_tx<'a, I: Signers + ?Sized>(
+        &self,
+        transaction: &mut VersionedTransaction,
+        signers: &'a I,
+    ) -> Result<u32, Box<dyn std::error::Error + 'static>>;
 
-        //Get Used CUs
-        let mut used_cu = 0u64;
+    fn optimize_compute_units_versioned_tx_with_config<'a, I: Signers + ?Sized>(
+        &self,
+        transaction: &mut VersionedTransaction,
+        signers: &'a I,
+        config: &OptimizeConfig,
+    ) -> Result<ComputeUnitLimit, Box<dyn std::error::Error + 'static>>;
 
-        //Get your message processor
+    fn optimize_compute_units_versioned_msg<'a, I: Signers + ?Sized>(
+        &self,
+        message: &mut VersionedMessage,
+        signers: &'a I,
+    ) -> Result<u32, Box<dyn std::error::Error + 'static>>;
 
-        let result_msg = message_processor::process_message(
-            sanitized.unwrap().message(), //&impl SVMMessage
-            &vec![],                       //&[Vec<IndexOfAccount>]
-            &mut invoke_context,           //&mut InvokeContext,
-            &mut timings,                  //&mut ExecuteTimings,
-            &mut used_cu,                  // &mut u64,
-        );
+    fn optimize_compute_units_versioned_msg_with_config<'a, I: Signers + ?Sized>(
+        &self,
+        message: &mut VersionedMessage,
+        signers: &'a I,
+        config: &OptimizeConfig,
+    ) -> Result<ComputeUnitLimit, Box<dyn std::error::Error + 'static>>;
+}
 
-        Ok(used_cu)
+impl RpcClientExt for solana_client::rpc_client::RpcClient {
+    fn simulate_unsigned_tx<'a, I: Signers + ?Sized>(
+        &self,
+        transaction: &Transaction,
+        signers: &'a I,
+    ) -> Result<SimulationReport, Box<dyn std::error::Error + 'static>> {
+        self.simulate_unsigned_tx_with_config(
+            transaction,
+            signers,
+            &LocalSimulationConfig::default(),
+        )
     }
 
-    fn estimate_compute_units_msg<'a, I: Signers + ?Sized>(
+    /// Executes the transaction in a local SVM instance loaded with the cluster's
+    /// programs, accounts and sysvars, and reports the outcome of every instruction.
+    fn simulate_unsigned_tx_with_config<'a, I: Signers + ?Sized>(
+        &self,
+        transaction: &Transaction,
+        signers: &'a I,
+        config: &LocalSimulationConfig,
+    ) -> Result<SimulationReport, Box<dyn std::error::Error + 'static>> {
+        Ok(local::simulate_transaction(
+            self,
+            &VersionedTransaction::from(transaction.clone()),
+            &signers.try_pubkeys()?,
+            config,
+        )?)
+    }
+
+    fn simulate_msg<'a, I: Signers + ?Sized>(
         &self,
         message: &Message,
         signers: &'a I,
-    ) -> Result<u64, Box<dyn std::error::Error + 'static>> {
-        let config = RpcSimulateTransactionConfig {
-            sig_verify: true,
-            ..RpcSimulateTransactionConfig::default()
-        };
+    ) -> Result<SimulationReport, Box<dyn std::error::Error + 'static>> {
+        self.simulate_msg_with_config(message, signers, &RpcSimulationConfig::default())
+    }
+
+    /// Simulates the signed message on the RPC node, returning its logs,
+    /// return data and inner instructions, and the changes made to its accounts
+    /// if `config` asks for them.
+    fn simulate_msg_with_config<'a, I: Signers + ?Sized>(
+        &self,
+        message: &Message,
+        signers: &'a I,
+        config: &RpcSimulationConfig,
+    ) -> Result<SimulationReport, Box<dyn std::error::Error + 'static>> {
         let mut tx = Transaction::new_unsigned(message.clone());
         tx.sign(signers, self.get_latest_blockhash()?);
-        let result = self.simulate_transaction_with_config(&tx, config)?;
+        let result = self.simulate_transaction_with_config(
+            &tx,
+            SimulationReport::rpc_config(&message.account_keys, true, config),
+        )?;
+        let message_accounts =
+            rpc_message_accounts(self, &message.account_keys, result.context.slot, config)?;
+
+        Ok(SimulationReport::from_rpc(
+            result.value,
+            &message.account_keys,
+            message_accounts,
+            &message.instructions,
+        )?)
+    }
+
+    fn estimate_compute_units_unsigned_tx<'a, I: Signers + ?Sized>(
+        &self,
+        transaction: &Transaction,
+        signers: &'a I,
+    ) -> Result<u64, Box<dyn std::error::Error + 'static>> {
+        self.estimate_compute_units_unsigned_tx_with_config(
+            transaction,
+            signers,
+            &LocalSimulationConfig::default(),
+        )
+    }
+
+    fn estimate_compute_units_unsigned_tx_with_config<'a, I: Signers + ?Sized>(
+        &self,
+        transaction: &Transaction,
+        signers: &'a I,
+        config: &LocalSimulationConfig,
+    ) -> Result<u64, Box<dyn std::error::Error + 'static>> {
+        let report = self.simulate_unsigned_tx_with_config(transaction, signers, config)?;
+        report.check()?;
 
-        let consumed_cu = result.value.units_consumed.ok_or(Box::new(
-            SolanaClientExtError::ComputeUnitsError(
-                "Missing Compute Units from transaction simulation.".into(),
-            ),
-        ))?;
+        Ok(report.units_consumed)
+    }
+
+    fn estimate_compute_units_msg<'a, I: Signers + ?Sized>(
+        &self,
+        message: &Message,
+        signers: &'a I,
+    ) -> Result<u64, Box<dyn std::error::Error + 'static>> {
+        let report = self.simulate_msg(message, signers)?;
+        report.check()?;
+        let consumed_cu = report.units_consumed;
 
         if consumed_cu == 0 {
             return Err(Box::new(SolanaClientExtError::RpcError(
@@ -185,25 +339,101 @@ impl RpcClientExt for solana_client::rpc_client::RpcClient {
         Ok(consumed_cu)
     }
 
+    fn simulate_unsigned_msg(
+        &self,
+        message: &Message,
+    ) -> Result<SimulationReport, Box<dyn std::error::Error + 'static>> {
+        self.simulate_unsigned_msg_with_config(message, &RpcSimulationConfig::default())
+    }
+
+    /// Simulates the message on the RPC node without signatures.
+    /// The node skips signature verification and replaces the blockhash,
+    /// so messages can be simulated for any fee payer before they are signed.
+    fn simulate_unsigned_msg_with_config(
+        &self,
+        message: &Message,
+        config: &RpcSimulationConfig,
+    ) -> Result<SimulationReport, Box<dyn std::error::Error + 'static>> {
+        let tx = Transaction::new_unsigned(message.clone());
+        let result = self.simulate_transaction_with_config(
+            &tx,
+            SimulationReport::rpc_config(&message.account_keys, false, config),
+        )?;
+        let message_accounts =
+            rpc_message_accounts(self, &message.account_keys, result.context.slot, config)?;
+
+        Ok(SimulationReport::from_rpc(
+            result.value,
+            &message.account_keys,
+            message_accounts,
+            &message.instructions,
+        )?)
+    }
+
+    fn estimate_compute_units_unsigned_msg(
+        &self,
+        message: &Message,
+    ) -> Result<u64, Box<dyn std::error::Error + 'static>> {
+        let report = self.simulate_unsigned_msg(message)?;
+        report.check()?;
+        let consumed_cu = report.units_consumed;
+
+        if consumed_cu == 0 {
+            return Err(Box::new(SolanaClientExtError::RpcError(
+                "Transaction simulation failed.".into(),
+            )));
+        }
+
+        Ok(consumed_cu)
+    }
+
+    /// Computes the fee of the message in lamports, including precompile signatures and
+    /// the prioritization fee, and cross-checks it with the `getFeeForMessage` RPC method.
+    /// The blockhash of the message is replaced by the latest one for the RPC method.
+    /// Compute units are allocated to builtins according to the cluster's feature set.
+    fn estimate_fee_msg(
+        &self,
+        message: &Message,
+    ) -> Result<FeeEstimate, Box<dyn std::error::Error + 'static>> {
+        let mut message = message.clone();
+        message.recent_blockhash = self.get_latest_blockhash()?;
+        let cluster_fee = self.get_fee_for_message(&message)?;
+        let feature_set = feature_set::cluster_feature_set(self)?;
+
+        Ok(fee::estimate_fee(&message, cluster_fee, &feature_set)?)
+    }
+
     fn optimize_compute_units_unsigned_tx<'a, I: Signers + ?Sized>(
         &self,
         transaction: &mut Transaction,
         signers: &'a I,
     ) -> Result<u32, Box<dyn std::error::Error + 'static>> {
-        let optimal_cu =
-            u32::try_from(self.estimate_compute_units_unsigned_tx(transaction, signers)?)?;
-        let optimize_ix = ComputeBudgetInstruction::set_compute_unit_limit(
-            optimal_cu.saturating_add(optimal_cu.saturating_div(100) * 20),
-        );
-        transaction
-            .message
-            .account_keys
-            .push(solana_compute_budget_interface::id());
-        let compiled_ix = transaction.message.compile_instruction(&optimize_ix);
+        self.optimize_compute_units_unsigned_tx_with_config(
+            transaction,
+            signers,
+            &OptimizeConfig::default(),
+        )
+        .map(|limit| limit.units_consumed)
+    }
 
-        transaction.message.instructions.insert(0, compiled_ix);
+    fn optimize_compute_units_unsigned_tx_with_config<'a, I: Signers + ?Sized>(
+        &self,
+        transaction: &mut Transaction,
+        signers: &'a I,
+        config: &OptimizeConfig,
+    ) -> Result<ComputeUnitLimit, Box<dyn std::error::Error + 'static>> {
+        let optimal_cu = if config.allow_failed_transaction {
+            self.simulate_unsigned_tx(transaction, signers)?
+                .units_consumed
+        } else {
+            self.estimate_compute_units_unsigned_tx(transaction, signers)?
+        };
 
-        Ok(optimal_cu)
+        Ok(compute_budget::set_compute_unit_limit(
+            &mut transaction.message,
+            optimal_cu,
+            &config.margin,
+        )?)
     }
 
     /// Simulates the transaction to get compute units used for the transaction
@@ -248,21 +478,273 @@ impl RpcClientExt for solana_client::rpc_client::RpcClient {
         message: &mut Message,
         signers: &'a I,
     ) -> Result<u32, Box<dyn std::error::Error + 'static>> {
-        let optimal_cu = u32::try_from(self.estimate_compute_units_msg(message, signers)?)?;
-        let optimize_ix = ComputeBudgetInstruction::set_compute_unit_limit(
-            optimal_cu.saturating_add(150 /*optimal_cu.saturating_div(100)*100*/),
-        );
-        message.account_keys.push(solana_compute_budget_interface::id());
-        let compiled_ix = message.compile_instruction(&optimize_ix);
-        message.instructions.insert(0, compiled_ix);
+        self.optimize_compute_units_msg_with_config(message, signers, &OptimizeConfig::default())
+            .map(|limit| limit.units_consumed)
+    }
+
+    fn optimize_compute_units_msg_with_config<'a, I: Signers + ?Sized>(
+        &self,
+        message: &mut Message,
+        signers: &'a I,
+        config: &OptimizeConfig,
+    ) -> Result<ComputeUnitLimit, Box<dyn std::error::Error + 'static>> {
+        let optimal_cu = if config.allow_failed_transaction {
+            self.simulate_msg(message, signers)?.units_consumed
+        } else {
+            self.estimate_compute_units_msg(message, signers)?
+        };
+
+        Ok(compute_budget::set_compute_unit_limit(
+            message,
+            optimal_cu,
+            &config.margin,
+        )?)
+    }
+    fn optimize_priority_fee_unsigned_tx(
+        &self,
+        transaction: &mut Transaction,
+    ) -> Result<u64, Box<dyn std::error::Error + 'static>> {
+        self.optimize_priority_fee_unsigned_tx_with_config(
+            transaction,
+            &PriorityFeeConfig::default(),
+        )
+    }
+
+    fn optimize_priority_fee_unsigned_tx_with_config(
+        &self,
+        transaction: &mut Transaction,
+        config: &PriorityFeeConfig,
+    ) -> Result<u64, Box<dyn std::error::Error + 'static>> {
+        self.optimize_priority_fee_msg_with_config(&mut transaction.message, config)
+    }
+
+    /// Sets the compute unit price of the message to a percentile of the prioritization fees
+    /// paid in recent slots by transactions writing to the same accounts.
+    /// An existing `SetComputeUnitPrice` instruction is updated in place.
+    fn optimize_priority_fee_msg(
+        &self,
+        message: &mut Message,
+    ) -> Result<u64, Box<dyn std::error::Error + 'static>> {
+        self.optimize_priority_fee_msg_with_config(message, &PriorityFeeConfig::default())
+    }
+
+    fn optimize_priority_fee_msg_with_config(
+        &self,
+        message: &mut Message,
+        config: &PriorityFeeConfig,
+    ) -> Result<u64, Box<dyn std::error::Error + 'static>> {
+        let fees =
+            self.get_recent_prioritization_fees(&priority_fee::writable_accounts(message))?;
+        let micro_lamports = priority_fee::select(&fees, config.percentile);
+        compute_budget::set_compute_budget_instruction(
+            message,
+            ComputeBudgetInstruction::set_compute_unit_price(micro_lamports),
+        )?;
+
+        Ok(micro_lamports)
+    }
+
+    fn simulate_versioned_tx<'a, I: Signers + ?Sized>(
+        &self,
+        transaction: &VersionedTransaction,
+        signers: &'a I,
+    ) -> Result<SimulationReport, Box<dyn std::error::Error + 'static>> {
+        self.simulate_versioned_tx_with_config(
+            transaction,
+            signers,
+            &LocalSimulationConfig::default(),
+        )
+    }
+
+    /// Executes the versioned transaction in a local SVM instance, with the addresses
+    /// of its lookup tables resolved from the cluster.
+    fn simulate_versioned_tx_with_config<'a, I: Signers + ?Sized>(
+        &self,
+        transaction: &VersionedTransaction,
+        signers: &'a I,
+        config: &LocalSimulationConfig,
+    ) -> Result<SimulationReport, Box<dyn std::error::Error + 'static>> {
+        Ok(local::simulate_transaction(
+            self,
+            transaction,
+            &signers.try_pubkeys()?,
+            config,
+        )?)
+    }
+
+    fn simulate_versioned_msg<'a, I: Signers + ?Sized>(
+        &self,
+        message: &VersionedMessage,
+        signers: &'a I,
+    ) -> Result<SimulationReport, Box<dyn std::error::Error + 'static>> {
+        self.simulate_versioned_msg_with_config(message, signers, &RpcSimulationConfig::default())
+    }
+
+    fn simulate_versioned_msg_with_config<'a, I: Signers + ?Sized>(
+        &self,
+        message: &VersionedMessage,
+        signers: &'a I,
+        config: &RpcSimulationConfig,
+    ) -> Result<SimulationReport, Box<dyn std::error::Error + 'static>> {
+        let lookup_tables =
+            lookup_tables::load_lookup_tables(&RpcAccountSource::new(self), message)?;
+        let account_keys = lookup_tables::account_keys(message, &lookup_tables)?;
+        let mut message = message.clone();
+        message.set_recent_blockhash(self.get_latest_blockhash()?);
+        let tx = VersionedTransaction::try_new(message, signers)?;
+        let result = self.simulate_transaction_with_config(
+            &tx,
+            SimulationReport::rpc_config(&account_keys, true, config),
+        )?;
+        let message_accounts =
+            rpc_message_accounts(self, &account_keys, result.context.slot, config)?;
+
+        Ok(SimulationReport::from_rpc(
+            result.value,
+            &account_keys,
+            message_accounts,
+            tx.message.instructions(),
+        )?)
+    }
+
+    fn estimate_compute_units_versioned_tx<'a, I: Signers + ?Sized>(
+        &self,
+        transaction: &VersionedTransaction,
+        signers: &'a I,
+    ) -> Result<u64, Box<dyn std::error::Error + 'static>> {
+        self.estimate_compute_units_versioned_tx_with_config(
+            transaction,
+            signers,
+            &LocalSimulationConfig::default(),
+        )
+    }
+
+    fn estimate_compute_units_versioned_tx_with_config<'a, I: Signers + ?Sized>(
+        &self,
+        transaction: &VersionedTransaction,
+        signers: &'a I,
+        config: &LocalSimulationConfig,
+    ) -> Result<u64, Box<dyn std::error::Error + 'static>> {
+        let report = self.simulate_versioned_tx_with_config(transaction, signers, config)?;
+        report.check()?;
+
+        Ok(report.units_consumed)
+    }
+
+    fn estimate_compute_units_versioned_msg<'a, I: Signers + ?Sized>(
+        &self,
+        message: &VersionedMessage,
+        signers: &'a I,
+    ) -> Result<u64, Box<dyn std::error::Error + 'static>> {
+        let report = self.simulate_versioned_msg(message, signers)?;
+        report.check()?;
+        let consumed_cu = report.units_consumed;
+
+        if consumed_cu == 0 {
+            return Err(Box::new(SolanaClientExtError::RpcError(
+                "Transaction simulation failed.".into(),
+            )));
+        }
+
+        Ok(consumed_cu)
+    }
+
+    fn optimize_compute_units_versioned_tx<'a, I: Signers + ?Sized>(
+        &self,
+        transaction: &mut VersionedTransaction,
+        signers: &'a I,
+    ) -> Result<u32, Box<dyn std::error::Error + 'static>> {
+        self.optimize_compute_units_versioned_tx_with_config(
+            transaction,
+            signers,
+            &OptimizeConfig::default(),
+        )
+        .map(|limit| limit.units_consumed)
+    }
+
+    fn optimize_compute_units_versioned_tx_with_config<'a, I: Signers + ?Sized>(
+        &self,
+        transaction: &mut VersionedTransaction,
+        signers: &'a I,
+        config: &OptimizeConfig,
+    ) -> Result<ComputeUnitLimit, Box<dyn std::error::Error + 'static>> {
+        let optimal_cu = if config.allow_failed_transaction {
+            self.simulate_versioned_tx(transaction, signers)?
+                .units_consumed
+        } else {
+            self.estimate_compute_units_versioned_tx(transaction, signers)?
+        };
+        let lookup_tables =
+            lookup_tables::load_lookup_tables(&RpcAccountSource::new(self), &transaction.message)?;
+
+        Ok(compute_budget::set_versioned_compute_unit_limit(
+            &mut transaction.message,
+            optimal_cu,
+            &config.margin,
+            &lookup_tables,
+        )?)
+    }
+
+    fn optimize_compute_units_versioned_msg<'a, I: Signers + ?Sized>(
+        &self,
+        message: &mut VersionedMessage,
+        signers: &'a I,
+    ) -> Result<u32, Box<dyn std::error::Error + 'static>> {
+        self.optimize_compute_units_versioned_msg_with_config(
+            message,
+            signers,
+            &OptimizeConfig::default(),
+        )
+        .map(|limit| limit.units_consumed)
+    }
+
+    /// Same as `optimize_compute_units_msg_with_config` for versioned messages.
+    /// v0 messages are compiled again with the lookup tables they reference.
+    fn optimize_compute_units_versioned_msg_with_config<'a, I: Signers + ?Sized>(
+        &self,
+        message: &mut VersionedMessage,
+        signers: &'a I,
+        config: &OptimizeConfig,
+    ) -> Result<ComputeUnitLimit, Box<dyn std::error::Error + 'static>> {
+        let optimal_cu = if config.allow_failed_transaction {
+            self.simulate_versioned_msg(message, signers)?
+                .units_consumed
+        } else {
+            self.estimate_compute_units_versioned_msg(message, signers)?
+        };
+        let lookup_tables =
+            lookup_tables::load_lookup_tables(&RpcAccountSource::new(self), message)?;
+
+        Ok(compute_budget::set_versioned_compute_unit_limit(
+            message,
+            optimal_cu,
+            &config.margin,
+            &lookup_tables,
+        )?)
+    }
+}
 
-        Ok(optimal_cu)
+/// Reads the state of the message accounts before an RPC simulation which ran at `slot`,
+/// if `config` reports account diffs.
+fn rpc_message_accounts(
+    rpc_client: &solana_client::rpc_client::RpcClient,
+    account_keys: &[Pubkey],
+    slot: Slot,
+    config: &RpcSimulationConfig,
+) -> Result<account_source::MessageAccounts, SolanaClientExtError> {
+    if !config.account_diffs {
+        return Ok(Default::default());
     }
+    account_source::message_accounts(
+        &RpcAccountSource::with_min_context_slot(rpc_client, slot),
+        account_keys,
+    )
 }
 
 #[cfg(test)]
 mod tests {
-    use solana_sdk::{pubkey::Pubkey, signature::Keypair, signer::Signer, system_instruction};
+    use solana_sdk::{pubkey::Pubkey, signature::Keypair, signer::Signer};
+    use solana_system_interface::instruction as system_instruction;
 
     use super::*;
 
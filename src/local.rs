@@ -0,0 +1,879 @@
+use std::{
+    collections::{HashMap, HashSet},
+    rc::Rc,
+    sync::Arc,
+};
+
+use agave_feature_set::FeatureSet;
+use solana_account::{AccountSharedData, WritableAccount};
+use solana_bpf_loader_program::syscalls::{
+    create_program_runtime_environment_v1, create_program_runtime_environment_v2,
+};
+use solana_client::{nonblocking, rpc_client::RpcClient};
+use solana_compute_budget::compute_budget::ComputeBudget;
+use solana_compute_budget_instruction::instructions_processor::process_compute_budget_instructions;
+use solana_fee_structure::FeeStructure;
+use solana_hash::Hash;
+use solana_message::{AddressLookupTableAccount, SimpleAddressLoader};
+use solana_program_runtime::{
+    invoke_context::{EnvironmentConfig, InvokeContext},
+    loaded_programs::{ProgramCacheForTxBatch, ProgramRuntimeEnvironments},
+};
+use solana_pubkey::Pubkey;
+use solana_svm_transaction::svm_message::SVMMessage;
+use solana_timings::ExecuteTimings;
+use solana_transaction::{
+    sanitized::{MessageHash, SanitizedTransaction},
+    versioned::VersionedTransaction,
+};
+use solana_transaction_context::{TransactionContext, TransactionReturnData};
+use solana_transaction_error::TransactionError;
+use solana_transaction_status_client_types::UiInnerInstructions;
+
+use crate::{
+    account_source::{
+        self, AccountSource, AsyncAccountSource, AsyncRpcAccountSource, RpcAccountSource,
+    },
+    builtins,
+    config::{AccountOverride, LocalSimulationConfig, LogCollection},
+    error::SolanaClientExtError,
+    feature_set, fee, invocation, lookup_tables, message_processor, programs,
+    report::{self, SimulationReport},
+    sysvars::{Sysvars, SYSVAR_IDS},
+    token,
+};
+
+/// Cost the validator cost model charges to verify each transaction signature.
+const SIGNATURE_COST: u64 = 720;
+
+/// Cluster state a transaction is executed against.
+struct ExecutionState {
+    /// Accounts of the message, in message order.
+    accounts: Vec<(Pubkey, AccountSharedData)>,
+    /// Accounts of the message, with the ProgramData accounts of its upgradeable programs.
+    program_accounts: HashMap<Pubkey, AccountSharedData>,
+    /// Mints of the token accounts of the message which aren't message accounts.
+    mints: HashMap<Pubkey, AccountSharedData>,
+    sysvars: Sysvars,
+    feature_set: Arc<FeatureSet>,
+}
+
+/// Executes `transaction` in a local SVM instance, with account state fetched from the cluster.
+pub(crate) fn simulate_transaction(
+    rpc_client: &RpcClient,
+    transaction: &VersionedTransaction,
+    signers: &[Pubkey],
+    config: &LocalSimulationConfig,
+) -> Result<SimulationReport, SolanaClientExtError> {
+    simulate_transaction_with_source(
+        &RpcAccountSource::new(rpc_client),
+        transaction,
+        signers,
+        config,
+        Some(rpc_client.url()),
+    )
+}
+
+/// Same as [`simulate_transaction`], with the nonblocking client.
+///
+/// Accounts are read asynchronously, the transaction is then verified, compiled and
+/// executed on the blocking thread pool of the tokio runtime.
+pub(crate) async fn simulate_transaction_async(
+    rpc_client: &nonblocking::rpc_client::RpcClient,
+    transaction: &VersionedTransaction,
+    signers: &[Pubkey],
+    config: &LocalSimulationConfig,
+) -> Result<SimulationReport, SolanaClientExtError> {
+    let source = AsyncRpcAccountSource::new(rpc_client);
+    let mut loader = StateLoader::new(transaction, signers, config, Some(rpc_client.url()))?;
+    while let Some(pubkeys) = loader.request() {
+        let accounts = source.get_accounts(pubkeys).await?;
+        loader.receive(accounts)?;
+    }
+    let (sanitized, state) = loader.finish()?;
+    let config = config.clone();
+    tokio::task::spawn_blocking(move || execute(&sanitized, state, &config))
+        .await
+        .map_err(|err| SolanaClientExtError::RuntimeEnvironmentError(err.to_string()))?
+}
+
+/// Executes `transaction` in a local SVM instance, with account state read from `source`.
+///
+/// The cluster feature set is cached for `cluster_url` if `source` reads from a cluster.
+pub(crate) fn simulate_transaction_with_source(
+    source: &(impl AccountSource + ?Sized),
+    transaction: &VersionedTransaction,
+    signers: &[Pubkey],
+    config: &LocalSimulationConfig,
+    cluster_url: Option<String>,
+) -> Result<SimulationReport, SolanaClientExtError> {
+    let mut loader = StateLoader::new(transaction, signers, config, cluster_url)?;
+    while let Some(pubkeys) = loader.request() {
+        let accounts = source.get_accounts(pubkeys)?;
+        loader.receive(accounts)?;
+    }
+    let (sanitized, state) = loader.finish()?;
+    execute(&sanitized, state, config)
+}
+
+/// Accounts a [`StateLoader`] reads, in order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum LoadStep {
+    LookupTables,
+    MessageAccounts,
+    /// ProgramData accounts of upgradeable programs, they hold the program bytes,
+    /// read along with the mints of token accounts for their decimals.
+    ProgramDataAndMints,
+    Sysvars,
+    FeatureSet,
+    Done,
+}
+
+/// Reads the state a transaction is executed against, in steps, since the accounts
+/// read in a step depend on those read before.
+///
+/// The loader only asks for accounts, so the blocking and nonblocking simulations
+/// share it and only differ in how they read them.
+struct StateLoader<'a> {
+    transaction: &'a VersionedTransaction,
+    signers: &'a [Pubkey],
+    config: &'a LocalSimulationConfig,
+    /// RPC url the cluster feature set is cached for, `None` if it isn't cached.
+    cluster_url: Option<String>,
+    step: LoadStep,
+    /// Accounts to read for `step`.
+    pubkeys: Vec<Pubkey>,
+    sanitized: Option<SanitizedTransaction>,
+    accounts: Vec<(Pubkey, AccountSharedData)>,
+    /// Number of ProgramData accounts in the ProgramData and mints step.
+    programdata_len: usize,
+    programdata_accounts: Vec<(Pubkey, AccountSharedData)>,
+    mints: HashMap<Pubkey, AccountSharedData>,
+    sysvars: Sysvars,
+    feature_set: Option<Arc<FeatureSet>>,
+}
+
+impl<'a> StateLoader<'a> {
+    fn new(
+        transaction: &'a VersionedTransaction,
+        signers: &'a [Pubkey],
+        config: &'a LocalSimulationConfig,
+        cluster_url: Option<String>,
+    ) -> Result<Self, SolanaClientExtError> {
+        let mut loader = Self {
+            transaction,
+            signers,
+            config,
+            cluster_url,
+            step: LoadStep::LookupTables,
+            pubkeys: lookup_tables::lookup_table_keys(&transaction.message),
+            sanitized: None,
+            accounts: Vec::new(),
+            programdata_len: 0,
+            programdata_accounts: Vec::new(),
+            mints: HashMap::new(),
+            sysvars: Sysvars::default(),
+            feature_set: None,
+        };
+        loader.skip_empty_steps()?;
+        Ok(loader)
+    }
+
+    /// Accounts to read next, `None` once every account is read.
+    fn request(&self) -> Option<&[Pubkey]> {
+        (self.step != LoadStep::Done).then_some(self.pubkeys.as_slice())
+    }
+
+    /// Takes the `accounts` read for [`StateLoader::request`], in the same order,
+    /// with `None` for accounts that don't exist.
+    fn receive(
+        &mut self,
+        accounts: Vec<Option<AccountSharedData>>,
+    ) -> Result<(), SolanaClientExtError> {
+        self.advance(accounts)?;
+        self.skip_empty_steps()
+    }
+
+    fn finish(self) -> Result<(SanitizedTransaction, ExecutionState), SolanaClientExtError> {
+        let (Some(sanitized), Some(feature_set)) = (self.sanitized, self.feature_set) else {
+            return Err(SolanaClientExtError::AccountFetchError(
+                "Accounts were not read completely.".into(),
+            ));
+        };
+        Ok((
+            sanitized,
+            ExecutionState::new(
+                self.accounts,
+                self.programdata_accounts,
+                self.mints,
+                self.sysvars,
+                feature_set,
+            ),
+        ))
+    }
+
+    /// Completes the steps which have no accounts to read.
+    fn skip_empty_steps(&mut self) -> Result<(), SolanaClientExtError> {
+        while self.step != LoadStep::Done && self.pubkeys.is_empty() {
+            self.advance(Vec::new())?;
+        }
+        Ok(())
+    }
+
+    /// Completes the current step with the `accounts` read for it, and moves to the next one.
+    fn advance(
+        &mut self,
+        accounts: Vec<Option<AccountSharedData>>,
+    ) -> Result<(), SolanaClientExtError> {
+        let pubkeys = std::mem::take(&mut self.pubkeys);
+        let overrides = &self.config.account_overrides;
+        self.step = match self.step {
+            LoadStep::LookupTables => {
+                let lookup_tables = lookup_tables::decode_lookup_tables(&pubkeys, accounts)?;
+                let sanitized = sanitize_transaction(self.transaction, &lookup_tables)?;
+                check_signers(&sanitized, self.signers, self.config)?;
+                self.pubkeys = account_keys(&sanitized);
+                self.sanitized = Some(sanitized);
+                LoadStep::MessageAccounts
+            }
+            LoadStep::MessageAccounts => {
+                //missing accounts are empty default accounts, the same way a validator loads them
+                self.accounts = account_source::default_accounts(&pubkeys, accounts);
+                apply_overrides(&mut self.accounts, overrides);
+                self.pubkeys = programs::programdata_addresses(&self.accounts);
+                self.programdata_len = self.pubkeys.len();
+                self.pubkeys.extend(token::missing_mints(&self.accounts));
+                LoadStep::ProgramDataAndMints
+            }
+            LoadStep::ProgramDataAndMints => {
+                let (programdata_addresses, mint_addresses) =
+                    pubkeys.split_at(self.programdata_len);
+                let mut accounts = accounts;
+                let mints = accounts.split_off(self.programdata_len);
+                self.programdata_accounts =
+                    account_source::default_accounts(programdata_addresses, accounts);
+                apply_overrides(&mut self.programdata_accounts, overrides);
+                self.mints = account_source::existing_accounts(mint_addresses, mints);
+                if self.config.sysvars.is_none() {
+                    self.pubkeys = SYSVAR_IDS.to_vec();
+                }
+                LoadStep::Sysvars
+            }
+            LoadStep::Sysvars => {
+                self.sysvars = match &self.config.sysvars {
+                    Some(sysvars) => sysvars.clone(),
+                    None => Sysvars::from_accounts(accounts),
+                };
+                self.feature_set = self
+                    .config
+                    .feature_set
+                    .known(self.cluster_url.as_deref(), self.sysvars.clock().epoch);
+                if self.feature_set.is_none() {
+                    self.pubkeys = feature_set::feature_ids();
+                }
+                LoadStep::FeatureSet
+            }
+            LoadStep::FeatureSet => {
+                if self.feature_set.is_none() {
+                    let feature_set =
+                        Arc::new(feature_set::feature_set_from_accounts(&pubkeys, accounts));
+                    if let Some(url) = self.cluster_url.take() {
+                        feature_set::cache_feature_set(
+                            url,
+                            self.sysvars.clock().epoch,
+                            &feature_set,
+                        );
+                    }
+                    self.feature_set = Some(feature_set);
+                }
+                LoadStep::Done
+            }
+            LoadStep::Done => LoadStep::Done,
+        };
+        Ok(())
+    }
+}
+
+impl ExecutionState {
+    fn new(
+        accounts: Vec<(Pubkey, AccountSharedData)>,
+        programdata_accounts: Vec<(Pubkey, AccountSharedData)>,
+        mints: HashMap<Pubkey, AccountSharedData>,
+        sysvars: Sysvars,
+        feature_set: Arc<FeatureSet>,
+    ) -> Self {
+        let mut program_accounts: HashMap<_, _> = accounts.iter().cloned().collect();
+        program_accounts.extend(programdata_accounts);
+        Self {
+            accounts,
+            program_accounts,
+            mints,
+            sysvars,
+            feature_set,
+        }
+    }
+}
+
+/// Applies the overrides of `account_overrides` to `accounts`.
+///
+/// Overrides are applied as soon as accounts are read, so the ProgramData accounts
+/// and mints read next are those of the overridden state.
+fn apply_overrides(
+    accounts: &mut [(Pubkey, AccountSharedData)],
+    account_overrides: &HashMap<Pubkey, AccountOverride>,
+) {
+    for (pubkey, account) in accounts {
+        if let Some(account_override) = account_overrides.get(pubkey) {
+            account_override.apply(account);
+        }
+    }
+}
+
+// GET SVM MESSAGE, with the addresses loaded from `lookup_tables`
+fn sanitize_transaction(
+    transaction: &VersionedTransaction,
+    lookup_tables: &[AddressLookupTableAccount],
+) -> Result<SanitizedTransaction, SolanaClientExtError> {
+    let loaded_addresses = lookup_tables::loaded_addresses(&transaction.message, lookup_tables)?;
+    SanitizedTransaction::try_create(
+        transaction.clone(),
+        MessageHash::Compute,
+        Some(false),
+        SimpleAddressLoader::Enabled(loaded_addresses),
+        &HashSet::new(),
+    )
+    .map_err(SolanaClientExtError::SanitizeError)
+}
+
+/// Checks that `signers` and the signers declared in `config` cover every signer
+/// required by the message.
+fn check_signers(
+    sanitized: &SanitizedTransaction,
+    signers: &[Pubkey],
+    config: &LocalSimulationConfig,
+) -> Result<(), SolanaClientExtError> {
+    let num_required_signatures = usize::from(sanitized.message().header().num_required_signatures);
+    let missing_signers = sanitized
+        .message()
+        .account_keys()
+        .iter()
+        .take(num_required_signatures)
+        .filter(|pubkey| !signers.contains(pubkey) && !config.declared_signers.contains(pubkey))
+        .copied()
+        .collect::<Vec<_>>();
+    if !missing_signers.is_empty() {
+        return Err(SolanaClientExtError::MissingSignerError(missing_signers));
+    }
+    Ok(())
+}
+
+/// Static and loaded account keys of the transaction, in execution order.
+fn account_keys(sanitized: &SanitizedTransaction) -> Vec<Pubkey> {
+    sanitized.message().account_keys().iter().copied().collect()
+}
+
+/// Runs the sanitized transaction through the program runtime against `state`.
+fn execute(
+    sanitized: &SanitizedTransaction,
+    state: ExecutionState,
+    config: &LocalSimulationConfig,
+) -> Result<SimulationReport, SolanaClientExtError> {
+    let ExecutionState {
+        accounts: mut accounts_data,
+        program_accounts,
+        mints,
+        sysvars,
+        feature_set,
+    } = state;
+    let accounts = accounts_data
+        .iter()
+        .map(|(pubkey, _)| *pubkey)
+        .collect::<Vec<_>>();
+    let pre_accounts = accounts_data.clone();
+
+    //The fee payer is charged before execution, the same as on a validator
+    let fee = fee::sanitized_message_fee(sanitized.message(), &feature_set)?.total_fee;
+    if let Some((_, fee_payer)) = accounts_data.first_mut() {
+        fee_payer.checked_sub_lamports(fee).map_err(|_| {
+            SolanaClientExtError::TransactionError(TransactionError::InsufficientFundsForFee)
+        })?;
+    }
+
+    //Limits requested by the compute budget instructions, the heap cost is charged
+    //by the program runtime from the requested heap size
+    let compute_budget_limits = process_compute_budget_instructions(
+        SVMMessage::program_instructions_iter(sanitized.message()),
+        &feature_set,
+    )
+    .map_err(SolanaClientExtError::TransactionError)?;
+    let compute_budget = ComputeBudget::from(compute_budget_limits);
+    let fee_structure = FeeStructure::default();
+    let lamports_per_signature = fee_structure.lamports_per_signature;
+
+    let sysvar_c = sysvars.sysvar_cache();
+    let clock = sysvar_c.get_clock().unwrap_or_default();
+    let rent = sysvar_c.get_rent().unwrap_or_default();
+
+    let runtime_features = feature_set.runtime_features();
+
+    // Get Invoke context
+    let mut transaction_context = TransactionContext::new(
+        accounts_data,
+        (*rent).clone(),
+        compute_budget.max_instruction_stack_depth,
+        compute_budget.max_instruction_trace_length,
+    );
+
+    //Rent collection is deprecated, the rent collector only checks rent state transitions
+    #[allow(deprecated)]
+    let rent_collector = solana_rent_collector::RentCollector {
+        rent: (*rent).clone(),
+        ..solana_rent_collector::RentCollector::default()
+    };
+    let pre_rent_states =
+        message_processor::rent_states(&transaction_context, sanitized.message(), &rent_collector);
+
+    let environments = ProgramRuntimeEnvironments {
+        program_runtime_v1: Arc::new(
+            create_program_runtime_environment_v1(
+                &runtime_features,
+                &compute_budget.to_budget(),
+                false,
+                false,
+            )
+            .map_err(|err| SolanaClientExtError::RuntimeEnvironmentError(err.to_string()))?,
+        ),
+        program_runtime_v2: Arc::new(create_program_runtime_environment_v2(
+            &compute_budget.to_budget(),
+            false,
+        )),
+    };
+    let callbacks = builtins::Precompiles(&feature_set);
+
+    let env_config = EnvironmentConfig::new(
+        Hash::default(),
+        lamports_per_signature,
+        &callbacks,
+        &runtime_features,
+        &sysvar_c,
+    );
+
+    //Get prog_cache
+    let mut prog_cache = ProgramCacheForTxBatch::new(
+        clock.slot, //Slot
+        environments.clone(),
+        None,        //Option<ProgramRuntimeEnvironments>
+        clock.epoch, //Epoch
+    );
+    builtins::register_builtins(&mut prog_cache);
+    programs::load_programs(
+        &accounts,
+        &program_accounts,
+        &environments,
+        clock.slot,
+        &mut prog_cache,
+    );
+
+    let log_collector = config.logs.log_collector();
+    let mut invoke_context = InvokeContext::new(
+        &mut transaction_context,    //&'a mut TransactionContext,
+        &mut prog_cache,             //&'a mut ProgramCacheForTxBatch,
+        env_config,                  //EnvironmentConfig<'a>,
+        Some(log_collector.clone()), //Option<Rc<RefCell<LogCollector>>>,
+        compute_budget.to_budget(),  //SVMTransactionExecutionBudget
+        compute_budget.to_cost(),    //SVMTransactionExecutionCost
+    );
+
+    // Get Timmings
+    let mut timings = ExecuteTimings::default();
+
+    //Get Used CUs
+    let mut used_cu = 0u64;
+    let mut instruction_cu = Vec::new();
+
+    //Get your message processor
+    let program_indices = message_processor::program_indices(sanitized.message());
+    let result = message_processor::process_message(
+        sanitized.message(), //&impl SVMMessage
+        &program_indices,    //&[Vec<IndexOfAccount>]
+        &mut invoke_context, //&mut InvokeContext,
+        &mut timings,        //&mut ExecuteTimings,
+        &mut used_cu,        // &mut u64,
+        &mut instruction_cu, // &mut Vec<u64>,
+    );
+
+    drop(invoke_context);
+
+    //Accounts can't be left below the rent-exempt minimum, the same as on a validator
+    let result = result.and_then(|()| {
+        let post_rent_states = message_processor::rent_states(
+            &transaction_context,
+            sanitized.message(),
+            &rent_collector,
+        );
+        message_processor::verify_rent_state_changes(
+            &pre_rent_states,
+            &post_rent_states,
+            &transaction_context,
+            &rent_collector,
+        )
+    });
+
+    let mut logs = Rc::try_unwrap(log_collector)
+        .map(|log_collector| log_collector.into_inner().into_messages())
+        .unwrap_or_default();
+
+    let (return_program_id, return_data) = transaction_context.get_return_data();
+    let return_data = (!return_data.is_empty()).then(|| TransactionReturnData {
+        program_id: *return_program_id,
+        data: return_data.to_vec(),
+    });
+    let inner_instructions = message_processor::inner_instructions(&transaction_context)
+        .into_iter()
+        .map(UiInnerInstructions::from)
+        .collect();
+    let mut invocations = message_processor::invocations(&transaction_context, &instruction_cu);
+    invocation::annotate_invocations(&mut invocations, &logs, &result);
+    if config.logs == LogCollection::Disabled {
+        logs.clear();
+        for invocation in &mut invocations {
+            invocation.logs.clear();
+        }
+    }
+    let accounts: Vec<_> = accounts
+        .iter()
+        .copied()
+        .zip(
+            transaction_context
+                .deconstruct_without_keys()
+                .map_err(|err| SolanaClientExtError::RuntimeEnvironmentError(err.to_string()))?,
+        )
+        .collect();
+
+    //Signatures are verified outside of the program runtime
+    if config.charge_signature_verification {
+        let num_signatures = u64::from(sanitized.message().header().num_required_signatures);
+        used_cu = used_cu.saturating_add(num_signatures.saturating_mul(SIGNATURE_COST));
+    }
+
+    Ok(SimulationReport {
+        units_consumed: used_cu,
+        instruction_compute_units: invocation::instruction_compute_units(&invocations),
+        invocations: invocation::invocation_tree(invocations),
+        result,
+        logs,
+        return_data,
+        inner_instructions,
+        account_diffs: report::account_diffs(&pre_accounts, &accounts),
+        token_balance_changes: token::balance_changes(&pre_accounts, &accounts, &mints),
+        accounts,
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use solana_account::{ReadableAccount, WritableAccount};
+    use solana_compute_budget_interface::ComputeBudgetInstruction;
+    use solana_instruction::{error::InstructionError, Instruction};
+    use solana_nonce::{
+        state::{Data, DurableNonce, State},
+        versions::Versions,
+    };
+    use solana_precompile_error::PrecompileError;
+    use solana_sdk::{signature::Keypair, signer::Signer};
+    use solana_system_interface::instruction as system_instruction;
+    use solana_transaction::Transaction;
+    use solana_transaction_error::TransactionError;
+
+    use super::*;
+    use crate::{account_source::AccountSnapshot, AccountStatus, FeatureSetSource};
+
+    /// Snapshot with a funded `payer`, the system and compute budget programs, along with
+    /// a transfer from `payer` to a new account.
+    pub(crate) fn transfer_snapshot(payer: &Keypair) -> (AccountSnapshot, Transaction) {
+        let mut builtin_program =
+            AccountSharedData::new(1, 0, &solana_sdk_ids::native_loader::id());
+        builtin_program.set_executable(true);
+        let snapshot = AccountSnapshot::new([
+            (
+                payer.pubkey(),
+                AccountSharedData::new(1_000_000_000, 0, &solana_sdk_ids::system_program::id()),
+            ),
+            (
+                solana_sdk_ids::system_program::id(),
+                builtin_program.clone(),
+            ),
+            (solana_sdk_ids::compute_budget::id(), builtin_program),
+        ]);
+        let transfer =
+            system_instruction::transfer(&payer.pubkey(), &Pubkey::new_unique(), 1_000_000);
+        let transaction = Transaction::new_with_payer(&[transfer], Some(&payer.pubkey()));
+        (snapshot, transaction)
+    }
+
+    /// Simulates `transaction` against `snapshot`, signed by every signer of its message.
+    fn simulate(
+        snapshot: &AccountSnapshot,
+        transaction: &Transaction,
+        config: &LocalSimulationConfig,
+    ) -> Result<SimulationReport, SolanaClientExtError> {
+        let num_signers = usize::from(transaction.message.header.num_required_signatures);
+        simulate_transaction_with_source(
+            snapshot,
+            &VersionedTransaction::from(transaction.clone()),
+            &transaction.message.account_keys[..num_signers],
+            config,
+            None,
+        )
+    }
+
+    /// Transfer from `sender` in `transfer_snapshot`, so `payer` and `sender` both sign.
+    fn two_signer_transfer(payer: &Keypair, sender: &Keypair) -> (AccountSnapshot, Transaction) {
+        let (mut snapshot, _) = transfer_snapshot(payer);
+        snapshot.insert(
+            sender.pubkey(),
+            AccountSharedData::new(1_000_000_000, 0, &solana_sdk_ids::system_program::id()),
+        );
+        let transfer =
+            system_instruction::transfer(&sender.pubkey(), &Pubkey::new_unique(), 1_000_000);
+        let transaction = Transaction::new_with_payer(&[transfer], Some(&payer.pubkey()));
+        (snapshot, transaction)
+    }
+
+    #[test]
+    fn rejects_missing_signer() {
+        let (payer, sender) = (Keypair::new(), Keypair::new());
+        let (snapshot, transaction) = two_signer_transfer(&payer, &sender);
+
+        let err = simulate_transaction_with_source(
+            &snapshot,
+            &VersionedTransaction::from(transaction),
+            &[payer.pubkey()],
+            &LocalSimulationConfig::default(),
+            None,
+        )
+        .unwrap_err();
+        assert!(
+            matches!(err, SolanaClientExtError::MissingSignerError(ref missing) if *missing == [sender.pubkey()])
+        );
+    }
+
+    #[test]
+    fn accepts_declared_signer() {
+        let (payer, sender) = (Keypair::new(), Keypair::new());
+        let (snapshot, transaction) = two_signer_transfer(&payer, &sender);
+        let config = LocalSimulationConfig {
+            declared_signers: vec![sender.pubkey()],
+            ..Default::default()
+        };
+
+        let report = simulate_transaction_with_source(
+            &snapshot,
+            &VersionedTransaction::from(transaction),
+            &[payer.pubkey()],
+            &config,
+            None,
+        )
+        .unwrap();
+        assert_eq!(report.result, Ok(()));
+    }
+
+    #[test]
+    fn applies_requested_compute_unit_limit() {
+        let payer = Keypair::new();
+        let (snapshot, _) = transfer_snapshot(&payer);
+        let transaction = Transaction::new_with_payer(
+            &[
+                ComputeBudgetInstruction::set_compute_unit_limit(100),
+                system_instruction::transfer(&payer.pubkey(), &Pubkey::new_unique(), 1_000_000),
+            ],
+            Some(&payer.pubkey()),
+        );
+        let config = LocalSimulationConfig {
+            feature_set: FeatureSetSource::AllEnabled,
+            ..LocalSimulationConfig::default()
+        };
+
+        let report = simulate(&snapshot, &transaction, &config).unwrap();
+        assert_eq!(
+            report.result,
+            Err(TransactionError::InstructionError(
+                0,
+                InstructionError::ComputationalBudgetExceeded
+            ))
+        );
+    }
+
+    #[test]
+    fn rejects_accounts_left_below_rent_exemption() {
+        let payer = Keypair::new();
+        let (snapshot, _) = transfer_snapshot(&payer);
+        let transfer = system_instruction::transfer(&payer.pubkey(), &Pubkey::new_unique(), 1);
+        let transaction = Transaction::new_with_payer(&[transfer], Some(&payer.pubkey()));
+        let config = LocalSimulationConfig {
+            feature_set: FeatureSetSource::AllEnabled,
+            ..LocalSimulationConfig::default()
+        };
+
+        let report = simulate(&snapshot, &transaction, &config).unwrap();
+        assert_eq!(
+            report.result,
+            Err(TransactionError::InsufficientFundsForRent { account_index: 1 })
+        );
+    }
+
+    #[test]
+    fn verifies_precompile_instructions() {
+        let payer = Keypair::new();
+        let (snapshot, _) = transfer_snapshot(&payer);
+        let config = LocalSimulationConfig {
+            feature_set: FeatureSetSource::AllEnabled,
+            ..LocalSimulationConfig::default()
+        };
+        let ed25519_instruction = |data: &[u8]| {
+            Transaction::new_with_payer(
+                &[Instruction::new_with_bytes(
+                    solana_sdk_ids::ed25519_program::id(),
+                    data,
+                    vec![],
+                )],
+                Some(&payer.pubkey()),
+            )
+        };
+
+        let report = simulate(&snapshot, &ed25519_instruction(&[0, 0]), &config).unwrap();
+        assert_eq!(report.result, Ok(()));
+        let report = simulate(&snapshot, &ed25519_instruction(&[1, 0]), &config).unwrap();
+        assert_eq!(
+            report.result,
+            Err(TransactionError::InstructionError(
+                0,
+                InstructionError::Custom(PrecompileError::InvalidInstructionDataSize as u32)
+            ))
+        );
+    }
+
+    #[test]
+    fn applies_account_overrides() {
+        let payer = Keypair::new();
+        let (snapshot, transaction) = transfer_snapshot(&payer);
+        let recipient = transaction.message.account_keys[1];
+        let config = LocalSimulationConfig {
+            feature_set: FeatureSetSource::AllEnabled,
+            account_overrides: HashMap::from([(
+                recipient,
+                AccountOverride {
+                    lamports: Some(1_000),
+                    data: Some(vec![1, 2, 3]),
+                    ..AccountOverride::default()
+                },
+            )]),
+            ..LocalSimulationConfig::default()
+        };
+
+        let report = simulate(&snapshot, &transaction, &config).unwrap();
+        let (_, account) = report
+            .accounts
+            .iter()
+            .find(|(pubkey, _)| *pubkey == recipient)
+            .unwrap();
+        assert_eq!(account.data(), [1, 2, 3]);
+        assert_eq!(account.lamports(), 1_001_000);
+        let diff = report
+            .account_diffs
+            .iter()
+            .find(|diff| diff.pubkey == recipient)
+            .unwrap();
+        assert_eq!(diff.status, AccountStatus::Modified);
+        assert_eq!(diff.lamports_delta, 1_000_000);
+        assert!(diff.data_changes.is_empty());
+    }
+
+    #[test]
+    fn charges_fee_payer() {
+        let payer = Keypair::new();
+        let (snapshot, transaction) = transfer_snapshot(&payer);
+
+        let report = simulate(&snapshot, &transaction, &LocalSimulationConfig::default()).unwrap();
+        let diff = report
+            .account_diffs
+            .iter()
+            .find(|diff| diff.pubkey == payer.pubkey())
+            .unwrap();
+        assert_eq!(diff.lamports_delta, -1_005_000);
+
+        let mut snapshot = snapshot;
+        snapshot.insert(
+            payer.pubkey(),
+            AccountSharedData::new(4_999, 0, &solana_sdk_ids::system_program::id()),
+        );
+        let err = simulate(&snapshot, &transaction, &LocalSimulationConfig::default()).unwrap_err();
+        assert!(matches!(
+            err,
+            SolanaClientExtError::TransactionError(TransactionError::InsufficientFundsForFee)
+        ));
+    }
+
+    #[test]
+    fn collects_program_logs() {
+        let payer = Keypair::new();
+        let (snapshot, transaction) = transfer_snapshot(&payer);
+        let config = LocalSimulationConfig {
+            feature_set: FeatureSetSource::AllEnabled,
+            logs: LogCollection::Unlimited,
+            ..LocalSimulationConfig::default()
+        };
+
+        let report = simulate(&snapshot, &transaction, &config).unwrap();
+        let system_program = solana_sdk_ids::system_program::id();
+        assert_eq!(
+            report.logs,
+            [
+                format!("Program {system_program} invoke [1]"),
+                format!("Program {system_program} success"),
+            ]
+        );
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn advances_durable_nonce() {
+        use solana_sdk::sysvar::recent_blockhashes::{IterItem, RecentBlockhashes};
+
+        let payer = Keypair::new();
+        let (mut snapshot, _) = transfer_snapshot(&payer);
+        let nonce = Pubkey::new_unique();
+        let durable_nonce = DurableNonce::from_blockhash(&Hash::new_unique());
+        let nonce_state = Versions::new(State::Initialized(Data::new(
+            payer.pubkey(),
+            durable_nonce,
+            5_000,
+        )));
+        let recent_blockhashes: RecentBlockhashes = [IterItem(0, &Hash::new_unique(), 5_000)]
+            .into_iter()
+            .collect();
+        snapshot.insert(
+            nonce,
+            AccountSharedData::new_data(
+                1_000_000,
+                &nonce_state,
+                &solana_sdk_ids::system_program::id(),
+            )
+            .unwrap(),
+        );
+        snapshot.insert(
+            solana_sdk_ids::sysvar::recent_blockhashes::id(),
+            AccountSharedData::new_data(1, &recent_blockhashes, &solana_sdk_ids::sysvar::id())
+                .unwrap(),
+        );
+        let advance = system_instruction::advance_nonce_account(&nonce, &payer.pubkey());
+        let transaction = Transaction::new_with_payer(&[advance], Some(&payer.pubkey()));
+        let config = LocalSimulationConfig {
+            feature_set: FeatureSetSource::AllEnabled,
+            ..LocalSimulationConfig::default()
+        };
+
+        let report = simulate(&snapshot, &transaction, &config).unwrap();
+        assert_eq!(report.result, Ok(()));
+    }
+}
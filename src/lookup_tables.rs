@@ -0,0 +1,118 @@
+use solana_account::ReadableAccount;
+use solana_address_lookup_table_interface::state::AddressLookupTable;
+use solana_message::{v0::LoadedAddresses, AddressLookupTableAccount, VersionedMessage};
+use solana_pubkey::Pubkey;
+
+use crate::{
+    account_source::{AccountSource, AsyncAccountSource},
+    error::SolanaClientExtError,
+};
+
+/// Reads the address lookup tables referenced by `message` from `source`.
+pub(crate) fn load_lookup_tables(
+    source: &(impl AccountSource + ?Sized),
+    message: &VersionedMessage,
+) -> Result<Vec<AddressLookupTableAccount>, SolanaClientExtError> {
+    let keys = lookup_table_keys(message);
+    if keys.is_empty() {
+        return Ok(Vec::new());
+    }
+    decode_lookup_tables(&keys, source.get_accounts(&keys)?)
+}
+
+/// Same as [`load_lookup_tables`], reading the tables from an [`AsyncAccountSource`].
+pub(crate) async fn load_lookup_tables_async(
+    source: &(impl AsyncAccountSource + ?Sized),
+    message: &VersionedMessage,
+) -> Result<Vec<AddressLookupTableAccount>, SolanaClientExtError> {
+    let keys = lookup_table_keys(message);
+    if keys.is_empty() {
+        return Ok(Vec::new());
+    }
+    decode_lookup_tables(&keys, source.get_accounts(&keys).await?)
+}
+
+/// Resolves the addresses `message` loads from `lookup_tables`.
+pub(crate) fn loaded_addresses(
+    message: &VersionedMessage,
+    lookup_tables: &[AddressLookupTableAccount],
+) -> Result<LoadedAddresses, SolanaClientExtError> {
+    let mut loaded_addresses = LoadedAddresses::default();
+    for lookup in message.address_table_lookups().unwrap_or_default() {
+        let table = lookup_tables
+            .iter()
+            .find(|table| table.key == lookup.account_key)
+            .ok_or_else(|| {
+                SolanaClientExtError::AddressLookupTableError(format!(
+                    "{} was not fetched.",
+                    lookup.account_key
+                ))
+            })?;
+        let lookup_address = |index: &u8| {
+            table
+                .addresses
+                .get(usize::from(*index))
+                .copied()
+                .ok_or_else(|| {
+                    SolanaClientExtError::AddressLookupTableError(format!(
+                        "{} has no address at index {index}.",
+                        table.key
+                    ))
+                })
+        };
+        for index in &lookup.writable_indexes {
+            loaded_addresses.writable.push(lookup_address(index)?);
+        }
+        for index in &lookup.readonly_indexes {
+            loaded_addresses.readonly.push(lookup_address(index)?);
+        }
+    }
+    Ok(loaded_addresses)
+}
+
+/// Static and loaded account keys of `message`, in execution order.
+pub(crate) fn account_keys(
+    message: &VersionedMessage,
+    lookup_tables: &[AddressLookupTableAccount],
+) -> Result<Vec<Pubkey>, SolanaClientExtError> {
+    let loaded_addresses = loaded_addresses(message, lookup_tables)?;
+    Ok(message
+        .static_account_keys()
+        .iter()
+        .chain(&loaded_addresses.writable)
+        .chain(&loaded_addresses.readonly)
+        .copied()
+        .collect())
+}
+
+/// Addresses of the lookup tables referenced by `message`.
+pub(crate) fn lookup_table_keys(message: &VersionedMessage) -> Vec<Pubkey> {
+    message
+        .address_table_lookups()
+        .unwrap_or_default()
+        .iter()
+        .map(|lookup| lookup.account_key)
+        .collect()
+}
+
+/// Decodes the lookup tables read at `keys`, failing on tables that don't exist.
+pub(crate) fn decode_lookup_tables<T: ReadableAccount>(
+    keys: &[Pubkey],
+    accounts: Vec<Option<T>>,
+) -> Result<Vec<AddressLookupTableAccount>, SolanaClientExtError> {
+    keys.iter()
+        .zip(accounts)
+        .map(|(key, account)| {
+            let account = account.ok_or_else(|| {
+                SolanaClientExtError::AddressLookupTableError(format!("{key} doesn't exist."))
+            })?;
+            let table = AddressLookupTable::deserialize(account.data()).map_err(|err| {
+                SolanaClientExtError::AddressLookupTableError(format!("{key}: {err}"))
+            })?;
+            Ok(AddressLookupTableAccount {
+                key: *key,
+                addresses: table.addresses.to_vec(),
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,232 @@
+use solana_instruction::TRANSACTION_LEVEL_STACK_HEIGHT;
+use solana_message::compiled_instruction::CompiledInstruction;
+use solana_program_runtime::invoke_context::InvokeContext;
+use solana_svm_rent_collector::{rent_state::RentState, svm_rent_collector::SVMRentCollector};
+use solana_svm_transaction::svm_message::SVMMessage;
+use solana_timings::{ExecuteDetailsTimings, ExecuteTimings};
+use solana_transaction_context::{IndexOfAccount, InstructionAccount, TransactionContext};
+use solana_transaction_error::TransactionError;
+use solana_transaction_status_client_types::{InnerInstruction, InnerInstructions};
+
+use crate::invocation::InvocationNode;
+
+/// Process a message against the given invoke context.
+///
+/// Mirrors `solana_svm::message_processor::process_message`, which is not exported,
+/// so the local estimator can run each instruction through the program runtime
+/// and accumulate the compute units it consumed.
+pub(crate) fn process_message(
+    message: &impl SVMMessage,
+    program_indices: &[Vec<IndexOfAccount>],
+    invoke_context: &mut InvokeContext,
+    execute_timings: &mut ExecuteTimings,
+    accumulated_consumed_units: &mut u64,
+    instruction_consumed_units: &mut Vec<u64>,
+) -> Result<(), TransactionError> {
+    for (top_level_instruction_index, ((program_id, instruction), program_indices)) in message
+        .program_instructions_iter()
+        .zip(program_indices.iter())
+        .enumerate()
+    {
+        let mut instruction_accounts = Vec::with_capacity(instruction.accounts.len());
+        for (instruction_account_index, index_in_transaction) in
+            instruction.accounts.iter().enumerate()
+        {
+            let index_in_callee = instruction
+                .accounts
+                .get(0..instruction_account_index)
+                .ok_or(TransactionError::InvalidAccountIndex)?
+                .iter()
+                .position(|account_index| account_index == index_in_transaction)
+                .unwrap_or(instruction_account_index)
+                as IndexOfAccount;
+            let index_in_transaction = *index_in_transaction as usize;
+            instruction_accounts.push(InstructionAccount {
+                index_in_transaction: index_in_transaction as IndexOfAccount,
+                index_in_caller: index_in_transaction as IndexOfAccount,
+                index_in_callee,
+                is_signer: message.is_signer(index_in_transaction),
+                is_writable: message.is_writable(index_in_transaction),
+            });
+        }
+
+        let mut compute_units_consumed = 0;
+        let result = if invoke_context.is_precompile(program_id) {
+            invoke_context.process_precompile(
+                program_id,
+                instruction.data,
+                &instruction_accounts,
+                program_indices,
+                message.instructions_iter().map(|ix| ix.data),
+            )
+        } else {
+            invoke_context.process_instruction(
+                instruction.data,
+                &instruction_accounts,
+                program_indices,
+                &mut compute_units_consumed,
+                execute_timings,
+            )
+        };
+
+        *accumulated_consumed_units =
+            accumulated_consumed_units.saturating_add(compute_units_consumed);
+        instruction_consumed_units.push(compute_units_consumed);
+        invoke_context.timings = {
+            execute_timings.details.accumulate(&invoke_context.timings);
+            ExecuteDetailsTimings::default()
+        };
+
+        result.map_err(|err| {
+            TransactionError::InstructionError(top_level_instruction_index as u8, err)
+        })?;
+    }
+    Ok(())
+}
+
+/// Rent state of every writable account of the message, `None` for readonly accounts.
+///
+/// Mirrors `solana_svm::transaction_account_state_info::TransactionAccountStateInfo::new`,
+/// which is not exported.
+pub(crate) fn rent_states(
+    transaction_context: &TransactionContext,
+    message: &impl SVMMessage,
+    rent_collector: &dyn SVMRentCollector,
+) -> Vec<Option<RentState>> {
+    (0..message.account_keys().len())
+        .map(|index| {
+            if !message.is_writable(index) {
+                return None;
+            }
+            transaction_context
+                .accounts()
+                .try_borrow(index as IndexOfAccount)
+                .ok()
+                .map(|account| rent_collector.get_account_rent_state(&account))
+        })
+        .collect()
+}
+
+/// Fails the transaction if a writable account moved to a rent state it isn't allowed to,
+/// like a new account funded below the rent-exempt minimum.
+pub(crate) fn verify_rent_state_changes(
+    pre_rent_states: &[Option<RentState>],
+    post_rent_states: &[Option<RentState>],
+    transaction_context: &TransactionContext,
+    rent_collector: &dyn SVMRentCollector,
+) -> Result<(), TransactionError> {
+    for (index, (pre_rent_state, post_rent_state)) in
+        pre_rent_states.iter().zip(post_rent_states).enumerate()
+    {
+        rent_collector.check_rent_state(
+            pre_rent_state.as_ref(),
+            post_rent_state.as_ref(),
+            transaction_context,
+            index as IndexOfAccount,
+        )?;
+    }
+    Ok(())
+}
+
+/// Index of each top level instruction's program account in the transaction accounts.
+pub(crate) fn program_indices(message: &impl SVMMessage) -> Vec<Vec<IndexOfAccount>> {
+    message
+        .instructions_iter()
+        .map(|instruction| vec![IndexOfAccount::from(instruction.program_id_index)])
+        .collect()
+}
+
+/// Lists every instruction of the instruction trace with the compute units metered
+/// for each top level instruction. CPIs are listed without compute units,
+/// the program runtime only meters top level instructions, they are read from the logs.
+pub(crate) fn invocations(
+    transaction_context: &TransactionContext,
+    instruction_consumed_units: &[u64],
+) -> Vec<InvocationNode> {
+    let mut invocations: Vec<InvocationNode> = Vec::new();
+    let mut instruction_index = 0;
+    for index_in_trace in 0..transaction_context.get_instruction_trace_length() {
+        let Ok(instruction_context) =
+            transaction_context.get_instruction_context_at_index_in_trace(index_in_trace)
+        else {
+            continue;
+        };
+        let stack_height = instruction_context.get_stack_height();
+        let is_top_level = stack_height == TRANSACTION_LEVEL_STACK_HEIGHT;
+        if is_top_level && !invocations.is_empty() {
+            instruction_index += 1;
+        }
+        let accounts = (0..instruction_context.get_number_of_instruction_accounts())
+            .map(|instruction_account_index| {
+                instruction_context
+                    .get_index_of_instruction_account_in_transaction(instruction_account_index)
+                    .ok()
+                    .and_then(|index| transaction_context.get_key_of_account_at_index(index).ok())
+                    .copied()
+                    .unwrap_or_default()
+            })
+            .collect();
+        let mut invocation = InvocationNode::new(
+            instruction_context
+                .get_last_program_key(transaction_context)
+                .copied()
+                .unwrap_or_default(),
+            instruction_context.get_instruction_data().to_vec(),
+            accounts,
+            stack_height,
+        );
+        if is_top_level {
+            invocation.units_consumed = instruction_consumed_units.get(instruction_index).copied();
+        }
+        invocations.push(invocation);
+    }
+    invocations
+}
+
+/// Collects the instructions invoked through CPI from the instruction trace,
+/// grouped by the top level instruction that issued them.
+pub(crate) fn inner_instructions(
+    transaction_context: &TransactionContext,
+) -> Vec<InnerInstructions> {
+    let mut outer_instructions: Vec<InnerInstructions> = Vec::new();
+    for index_in_trace in 0..transaction_context.get_instruction_trace_length() {
+        let Ok(instruction_context) =
+            transaction_context.get_instruction_context_at_index_in_trace(index_in_trace)
+        else {
+            continue;
+        };
+        let stack_height = instruction_context.get_stack_height();
+        if stack_height == TRANSACTION_LEVEL_STACK_HEIGHT {
+            outer_instructions.push(InnerInstructions {
+                index: outer_instructions.len() as u8,
+                instructions: Vec::new(),
+            });
+        } else if let Some(inner_instructions) = outer_instructions.last_mut() {
+            let instruction = CompiledInstruction::new_from_raw_parts(
+                instruction_context
+                    .get_index_of_program_account_in_transaction(
+                        instruction_context
+                            .get_number_of_program_accounts()
+                            .saturating_sub(1),
+                    )
+                    .unwrap_or_default() as u8,
+                instruction_context.get_instruction_data().to_vec(),
+                (0..instruction_context.get_number_of_instruction_accounts())
+                    .map(|instruction_account_index| {
+                        instruction_context
+                            .get_index_of_instruction_account_in_transaction(
+                                instruction_account_index,
+                            )
+                            .unwrap_or_default() as u8
+                    })
+                    .collect(),
+            );
+            inner_instructions.instructions.push(InnerInstruction {
+                instruction,
+                stack_height: u32::try_from(stack_height).ok(),
+            });
+        }
+    }
+    outer_instructions.retain(|inner_instructions| !inner_instructions.instructions.is_empty());
+    outer_instructions
+}
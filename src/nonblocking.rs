@@ -0,0 +1,709 @@
+use async_trait::async_trait;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_clock::Slot;
+use solana_compute_budget_interface::ComputeBudgetInstruction;
+use solana_message::{Message, VersionedMessage};
+use solana_pubkey::Pubkey;
+use solana_signer::signers::Signers;
+use solana_transaction::{versioned::VersionedTransaction, Transaction};
+
+use crate::{
+    account_source::{self, AsyncRpcAccountSource},
+    compute_budget,
+    config::{LocalSimulationConfig, OptimizeConfig, PriorityFeeConfig, RpcSimulationConfig},
+    error::SolanaClientExtError,
+    feature_set,
+    fee::{self, FeeEstimate},
+    local, lookup_tables, priority_fee,
+    report::{ComputeUnitLimit, SimulationReport},
+};
+
+/// # AsyncRpcClientExt
+///
+/// Same operations as [`RpcClientExt`](crate::RpcClientExt), for the nonblocking rust solana client.
+#[async_trait]
+pub trait AsyncRpcClientExt {
+    async fn simulate_unsigned_tx<'a, I: Signers + Sync + ?Sized>(
+        &self,
+        unsigned_transaction: &Transaction,
+        signers: &'a I,
+    ) -> Result<SimulationReport, Box<dyn std::error::Error + Send + Sync + 'static>>;
+
+    async fn simulate_unsigned_tx_with_config<'a, I: Signers + Sync + ?Sized>(
+        &self,
+        unsigned_transaction: &Transaction,
+        signers: &'a I,
+        config: &LocalSimulationConfig,
+    ) -> Result<SimulationReport, Box<dyn std::error::Error + Send + Sync + 'static>>;
+
+    async fn simulate_msg<'a, I: Signers + Sync + ?Sized>(
+        &self,
+        msg: &Message,
+        signers: &'a I,
+    ) -> Result<SimulationReport, Box<dyn std::error::Error + Send + Sync + 'static>>;
+
+    async fn simulate_msg_with_config<'a, I: Signers + Sync + ?Sized>(
+        &self,
+        msg: &Message,
+        signers: &'a I,
+        config: &RpcSimulationConfig,
+    ) -> Result<SimulationReport, Box<dyn std::error::Error + Send + Sync + 'static>>;
+
+    async fn estimate_compute_units_unsigned_tx<'a, I: Signers + Sync + ?Sized>(
+        &self,
+        unsigned_transaction: &Transaction,
+        signers: &'a I,
+    ) -> Result<u64, Box<dyn std::error::Error + Send + Sync + 'static>>;
+
+    async fn estimate_compute_units_unsigned_tx_with_config<'a, I: Signers + Sync + ?Sized>(
+        &self,
+        unsigned_transaction: &Transaction,
+        signers: &'a I,
+        config: &LocalSimulationConfig,
+    ) -> Result<u64, Box<dyn std::error::Error + Send + Sync + 'static>>;
+
+    async fn estimate_compute_units_msg<'a, I: Signers + Sync + ?Sized>(
+        &self,
+        msg: &Message,
+        signers: &'a I,
+    ) -> Result<u64, Box<dyn std::error::Error + Send + Sync + 'static>>;
+
+    async fn simulate_unsigned_msg(
+        &self,
+        msg: &Message,
+    ) -> Result<SimulationReport, Box<dyn std::error::Error + Send + Sync + 'static>>;
+
+    async fn simulate_unsigned_msg_with_config(
+        &self,
+        msg: &Message,
+        config: &RpcSimulationConfig,
+    ) -> Result<SimulationReport, Box<dyn std::error::Error + Send + Sync + 'static>>;
+
+    async fn estimate_compute_units_unsigned_msg(
+        &self,
+        msg: &Message,
+    ) -> Result<u64, Box<dyn std::error::Error + Send + Sync + 'static>>;
+
+    async fn estimate_fee_msg(
+        &self,
+        msg: &Message,
+    ) -> Result<FeeEstimate, Box<dyn std::error::Error + Send + Sync + 'static>>;
+
+    async fn optimize_compute_units_unsigned_tx<'a, I: Signers + Sync + ?Sized>(
+        &self,
+        unsigned_transaction: &mut Transaction,
+        signers: &'a I,
+    ) -> Result<u32, Box<dyn std::error::Error + Send + Sync + 'static>>;
+
+    async fn optimize_compute_units_unsigned_tx_with_config<'a, I: Signers + Sync + ?Sized>(
+        &self,
+        unsigned_transaction: &mut Transaction,
+        signers: &'a I,
+        config: &OptimizeConfig,
+    ) -> Result<ComputeUnitLimit, Box<dyn std::error::Error + Send + Sync + 'static>>;
+
+    async fn optimize_compute_units_msg<'a, I: Signers + Sync + ?Sized>(
+        &self,
+        message: &mut Message,
+        signers: &'a I,
+    ) -> Result<u32, Box<dyn std::error::Error + Send + Sync + 'static>>;
+
+    async fn optimize_compute_units_msg_with_config<'a, I: Signers + Sync + ?Sized>(
+        &self,
+        message: &mut Message,
+        signers: &'a I,
+        config: &OptimizeConfig,
+    ) -> Result<ComputeUnitLimit, Box<dyn std::error::Error + Send + Sync + 'static>>;
+
+    async fn optimize_priority_fee_unsigned_tx(
+        &self,
+        unsigned_transaction: &mut Transaction,
+    ) -> Result<u64, Box<dyn std::error::Error + Send + Sync + 'static>>;
+
+    async fn optimize_priority_fee_unsigned_tx_with_config(
+        &self,
+        unsigned_transaction: &mut Transaction,
+        config: &PriorityFeeConfig,
+    ) -> Result<u64, Box<dyn std::error::Error + Send + Sync + 'static>>;
+
+    async fn optimize_priority_fee_msg(
+        &self,
+        message: &mut Message,
+    ) -> Result<u64, Box<dyn std::error::Error + Send + Sync + 'static>>;
+
+    async fn optimize_priority_fee_msg_with_config(
+        &self,
+        message: &mut Message,
+        config: &PriorityFeeConfig,
+    ) -> Result<u64, Box<dyn std::error::Error + Send + Sync + 'static>>;
+
+    async fn simulate_versioned_tx<'a, I: Signers + Sync + ?Sized>(
+        &self,
+        transaction: &VersionedTransaction,
+        signers: &'a I,
+    ) -> Result<SimulationReport, Box<dyn std::error::Error + Send + Sync + 'static>>;
+
+    async fn simulate_versioned_tx_with_config<'a, I: Signers + Sync + ?Sized>(
+        &self,
+        transaction: &VersionedTransaction,
+        signers: &'a I,
+        config: &LocalSimulationConfig,
+    ) -> Result<SimulationReport, Box<dyn std::error::Error + Send + Sync + 'static>>;
+
+    async fn simulate_versioned_msg<'a, I: Signers + Sync + ?Sized>(
+        &self,
+        msg: &VersionedMessage,
+        signers: &'a I,
+    ) -> Result<SimulationReport, Box<dyn std::error::Error + Send + Sync + 'static>>;
+
+    async fn simulate_versioned_msg_with_config<'a, I: Signers + Sync + ?Sized>(
+        &self,
+        msg: &VersionedMessage,
+        signers: &'a I,
+        config: &RpcSimulationConfig,
+    ) -> Result<SimulationReport, Box<dyn std::error::Error + Send + Sync + 'static>>;
+
+    async fn estimate_compute_units_versioned_tx<'a, I: Signers + Sync + ?Sized>(
+        &self,
+        transaction: &VersionedTransaction,
+        signers: &'a I,
+    ) -> Result<u64, Box<dyn std::error::Error + Send + Sync + 'static>>;
+
+    async fn estimate_compute_units_versioned_tx_with_config<'a, I: Signers + Sync + ?Sized>(
+        &self,
+        transaction: &VersionedTransaction,
+        signers: &'a I,
+        config: &LocalSimulationConfig,
+    ) -> Result<u64, Box<dyn std::error::Error + Send + Sync + 'static>>;
+
+    async fn estimate_compute_units_versioned_msg<'a, I: Signers + Sync + ?Sized>(
+        &self,
+        msg: &VersionedMessage,
+        signers: &'a I,
+    ) -> Result<u64, Box<dyn std::error::Error + Send + Sync + 'static>>;
+
+    async fn optimize_compute_units_versioned_tx<'a, I: Signers + Sync + ?Sized>(
+        &self,
+        transaction: &mut VersionedTransaction,
+        signers: &'a I,
+    ) -> Result<u32, Box<dyn std::error::Error + Send + Sync + 'static>>;
+
+    async fn optimize_compute_units_versioned_tx_with_config<'a, I: Signers + Sync + ?Sized>(
+        &self,
+        transaction: &mut VersionedTransaction,
+        signers: &'a I,
+        config: &OptimizeConfig,
+    ) -> Result<ComputeUnitLimit, Box<dyn std::error::Error + Send + Sync + 'static>>;
+
+    async fn optimize_compute_units_versioned_msg<'a, I: Signers + Sync + ?Sized>(
+        &self,
+        message: &mut VersionedMessage,
+        signers: &'a I,
+    ) -> Result<u32, Box<dyn std::error::Error + Send + Sync + 'static>>;
+
+    async fn optimize_compute_units_versioned_msg_with_config<'a, I: Signers + Sync + ?Sized>(
+        &self,
+        message: &mut VersionedMessage,
+        signers: &'a I,
+        config: &OptimizeConfig,
+    ) -> Result<ComputeUnitLimit, Box<dyn std::error::Error + Send + Sync + 'static>>;
+}
+
+#[async_trait]
+impl AsyncRpcClientExt for RpcClient {
+    async fn simulate_unsigned_tx<'a, I: Signers + Sync + ?Sized>(
+        &self,
+        transaction: &Transaction,
+        signers: &'a I,
+    ) -> Result<SimulationReport, Box<dyn std::error::Error + Send + Sync + 'static>> {
+        self.simulate_unsigned_tx_with_config(
+            transaction,
+            signers,
+            &LocalSimulationConfig::default(),
+        )
+        .await
+    }
+
+    async fn simulate_unsigned_tx_with_config<'a, I: Signers + Sync + ?Sized>(
+        &self,
+        transaction: &Transaction,
+        signers: &'a I,
+        config: &LocalSimulationConfig,
+    ) -> Result<SimulationReport, Box<dyn std::error::Error + Send + Sync + 'static>> {
+        Ok(local::simulate_transaction_async(
+            self,
+            &VersionedTransaction::from(transaction.clone()),
+            &signers.try_pubkeys()?,
+            config,
+        )
+        .await?)
+    }
+
+    async fn simulate_msg<'a, I: Signers + Sync + ?Sized>(
+        &self,
+        message: &Message,
+        signers: &'a I,
+    ) -> Result<SimulationReport, Box<dyn std::error::Error + Send + Sync + 'static>> {
+        self.simulate_msg_with_config(message, signers, &RpcSimulationConfig::default())
+            .await
+    }
+
+    async fn simulate_msg_with_config<'a, I: Signers + Sync + ?Sized>(
+        &self,
+        message: &Message,
+        signers: &'a I,
+        config: &RpcSimulationConfig,
+    ) -> Result<SimulationReport, Box<dyn std::error::Error + Send + Sync + 'static>> {
+        let mut tx = Transaction::new_unsigned(message.clone());
+        tx.sign(signers, self.get_latest_blockhash().await?);
+        let result = self
+            .simulate_transaction_with_config(
+                &tx,
+                SimulationReport::rpc_config(&message.account_keys, true, config),
+            )
+            .await?;
+        let message_accounts =
+            rpc_message_accounts(self, &message.account_keys, result.context.slot, config).await?;
+
+        Ok(SimulationReport::from_rpc(
+            result.value,
+            &message.account_keys,
+            message_accounts,
+            &message.instructions,
+        )?)
+    }
+
+    async fn estimate_compute_units_unsigned_tx<'a, I: Signers + Sync + ?Sized>(
+        &self,
+        transaction: &Transaction,
+        signers: &'a I,
+    ) -> Result<u64, Box<dyn std::error::Error + Send + Sync + 'static>> {
+        self.estimate_compute_units_unsigned_tx_with_config(
+            transaction,
+            signers,
+            &LocalSimulationConfig::default(),
+        )
+        .await
+    }
+
+    async fn estimate_compute_units_unsigned_tx_with_config<'a, I: Signers + Sync + ?Sized>(
+        &self,
+        transaction: &Transaction,
+        signers: &'a I,
+        config: &LocalSimulationConfig,
+    ) -> Result<u64, Box<dyn std::error::Error + Send + Sync + 'static>> {
+        let report = self
+            .simulate_unsigned_tx_with_config(transaction, signers, config)
+            .await?;
+        report.check()?;
+
+        Ok(report.units_consumed)
+    }
+
+    async fn estimate_compute_units_msg<'a, I: Signers + Sync + ?Sized>(
+        &self,
+        message: &Message,
+        signers: &'a I,
+    ) -> Result<u64, Box<dyn std::error::Error + Send + Sync + 'static>> {
+        let report = self.simulate_msg(message, signers).await?;
+        report.check()?;
+        let consumed_cu = report.units_consumed;
+
+        if consumed_cu == 0 {
+            return Err(Box::new(SolanaClientExtError::RpcError(
+                "Transaction simulation failed.".into(),
+            )));
+        }
+
+        Ok(consumed_cu)
+    }
+
+    async fn simulate_unsigned_msg(
+        &self,
+        message: &Message,
+    ) -> Result<SimulationReport, Box<dyn std::error::Error + Send + Sync + 'static>> {
+        self.simulate_unsigned_msg_with_config(message, &RpcSimulationConfig::default())
+            .await
+    }
+
+    /// Simulates the message on the RPC node without signatures.
+    /// The node skips signature verification and replaces the blockhash,
+    /// so messages can be simulated for any fee payer before they are signed.
+    async fn simulate_unsigned_msg_with_config(
+        &self,
+        message: &Message,
+        config: &RpcSimulationConfig,
+    ) -> Result<SimulationReport, Box<dyn std::error::Error + Send + Sync + 'static>> {
+        let tx = Transaction::new_unsigned(message.clone());
+        let result = self
+            .simulate_transaction_with_config(
+                &tx,
+                SimulationReport::rpc_config(&message.account_keys, false, config),
+            )
+            .await?;
+        let message_accounts =
+            rpc_message_accounts(self, &message.account_keys, result.context.slot, config).await?;
+
+        Ok(SimulationReport::from_rpc(
+            result.value,
+            &message.account_keys,
+            message_accounts,
+            &message.instructions,
+        )?)
+    }
+
+    async fn estimate_compute_units_unsigned_msg(
+        &self,
+        message: &Message,
+    ) -> Result<u64, Box<dyn std::error::Error + Send + Sync + 'static>> {
+        let report = self.simulate_unsigned_msg(message).await?;
+        report.check()?;
+        let consumed_cu = report.units_consumed;
+
+        if consumed_cu == 0 {
+            return Err(Box::new(SolanaClientExtError::RpcError(
+                "Transaction simulation failed.".into(),
+            )));
+        }
+
+        Ok(consumed_cu)
+    }
+
+    /// Computes the fee of the message in lamports, including precompile signatures and
+    /// the prioritization fee, and cross-checks it with the `getFeeForMessage` RPC method.
+    /// The blockhash of the message is replaced by the latest one for the RPC method.
+    /// Compute units are allocated to builtins according to the cluster's feature set.
+    async fn estimate_fee_msg(
+        &self,
+        message: &Message,
+    ) -> Result<FeeEstimate, Box<dyn std::error::Error + Send + Sync + 'static>> {
+        let mut message = message.clone();
+        message.recent_blockhash = self.get_latest_blockhash().await?;
+        let cluster_fee = self.get_fee_for_message(&message).await?;
+        let feature_set = feature_set::cluster_feature_set_async(self).await?;
+
+        Ok(fee::estimate_fee(&message, cluster_fee, &feature_set)?)
+    }
+
+    async fn optimize_compute_units_unsigned_tx<'a, I: Signers + Sync + ?Sized>(
+        &self,
+        transaction: &mut Transaction,
+        signers: &'a I,
+    ) -> Result<u32, Box<dyn std::error::Error + Send + Sync + 'static>> {
+        self.optimize_compute_units_unsigned_tx_with_config(
+            transaction,
+            signers,
+            &OptimizeConfig::default(),
+        )
+        .await
+        .map(|limit| limit.units_consumed)
+    }
+
+    async fn optimize_compute_units_unsigned_tx_with_config<'a, I: Signers + Sync + ?Sized>(
+        &self,
+        transaction: &mut Transaction,
+        signers: &'a I,
+        config: &OptimizeConfig,
+    ) -> Result<ComputeUnitLimit, Box<dyn std::error::Error + Send + Sync + 'static>> {
+        let optimal_cu = if config.allow_failed_transaction {
+            self.simulate_unsigned_tx(transaction, signers)
+                .await?
+                .units_consumed
+        } else {
+            self.estimate_compute_units_unsigned_tx(transaction, signers)
+                .await?
+        };
+
+        Ok(compute_budget::set_compute_unit_limit(
+            &mut transaction.message,
+            optimal_cu,
+            &config.margin,
+        )?)
+    }
+
+    async fn optimize_compute_units_msg<'a, I: Signers + Sync + ?Sized>(
+        &self,
+        message: &mut Message,
+        signers: &'a I,
+    ) -> Result<u32, Box<dyn std::error::Error + Send + Sync + 'static>> {
+        self.optimize_compute_units_msg_with_config(message, signers, &OptimizeConfig::default())
+            .await
+            .map(|limit| limit.units_consumed)
+    }
+
+    async fn optimize_compute_units_msg_with_config<'a, I: Signers + Sync + ?Sized>(
+        &self,
+        message: &mut Message,
+        signers: &'a I,
+        config: &OptimizeConfig,
+    ) -> Result<ComputeUnitLimit, Box<dyn std::error::Error + Send + Sync + 'static>> {
+        let optimal_cu = if config.allow_failed_transaction {
+            self.simulate_msg(message, signers).await?.units_consumed
+        } else {
+            self.estimate_compute_units_msg(message, signers).await?
+        };
+
+        Ok(compute_budget::set_compute_unit_limit(
+            message,
+            optimal_cu,
+            &config.margin,
+        )?)
+    }
+
+    async fn optimize_priority_fee_unsigned_tx(
+        &self,
+        transaction: &mut Transaction,
+    ) -> Result<u64, Box<dyn std::error::Error + Send + Sync + 'static>> {
+        self.optimize_priority_fee_unsigned_tx_with_config(
+            transaction,
+            &PriorityFeeConfig::default(),
+        )
+        .await
+    }
+
+    async fn optimize_priority_fee_unsigned_tx_with_config(
+        &self,
+        transaction: &mut Transaction,
+        config: &PriorityFeeConfig,
+    ) -> Result<u64, Box<dyn std::error::Error + Send + Sync + 'static>> {
+        self.optimize_priority_fee_msg_with_config(&mut transaction.message, config)
+            .await
+    }
+
+    async fn optimize_priority_fee_msg(
+        &self,
+        message: &mut Message,
+    ) -> Result<u64, Box<dyn std::error::Error + Send + Sync + 'static>> {
+        self.optimize_priority_fee_msg_with_config(message, &PriorityFeeConfig::default())
+            .await
+    }
+
+    async fn optimize_priority_fee_msg_with_config(
+        &self,
+        message: &mut Message,
+        config: &PriorityFeeConfig,
+    ) -> Result<u64, Box<dyn std::error::Error + Send + Sync + 'static>> {
+        let fees = self
+            .get_recent_prioritization_fees(&priority_fee::writable_accounts(message))
+            .await?;
+        let micro_lamports = priority_fee::select(&fees, config.percentile);
+        compute_budget::set_compute_budget_instruction(
+            message,
+            ComputeBudgetInstruction::set_compute_unit_price(micro_lamports),
+        )?;
+
+        Ok(micro_lamports)
+    }
+
+    async fn simulate_versioned_tx<'a, I: Signers + Sync + ?Sized>(
+        &self,
+        transaction: &VersionedTransaction,
+        signers: &'a I,
+    ) -> Result<SimulationReport, Box<dyn std::error::Error + Send + Sync + 'static>> {
+        self.simulate_versioned_tx_with_config(
+            transaction,
+            signers,
+            &LocalSimulationConfig::default(),
+        )
+        .await
+    }
+
+    /// Executes the versioned transaction in a local SVM instance, with the addresses
+    /// of its lookup tables resolved from the cluster.
+    async fn simulate_versioned_tx_with_config<'a, I: Signers + Sync + ?Sized>(
+        &self,
+        transaction: &VersionedTransaction,
+        signers: &'a I,
+        config: &LocalSimulationConfig,
+    ) -> Result<SimulationReport, Box<dyn std::error::Error + Send + Sync + 'static>> {
+        Ok(
+            local::simulate_transaction_async(self, transaction, &signers.try_pubkeys()?, config)
+                .await?,
+        )
+    }
+
+    async fn simulate_versioned_msg<'a, I: Signers + Sync + ?Sized>(
+        &self,
+        message: &VersionedMessage,
+        signers: &'a I,
+    ) -> Result<SimulationReport, Box<dyn std::error::Error + Send + Sync + 'static>> {
+        self.simulate_versioned_msg_with_config(message, signers, &RpcSimulationConfig::default())
+            .await
+    }
+
+    async fn simulate_versioned_msg_with_config<'a, I: Signers + Sync + ?Sized>(
+        &self,
+        message: &VersionedMessage,
+        signers: &'a I,
+        config: &RpcSimulationConfig,
+    ) -> Result<SimulationReport, Box<dyn std::error::Error + Send + Sync + 'static>> {
+        let lookup_tables =
+            lookup_tables::load_lookup_tables_async(&AsyncRpcAccountSource::new(self), message)
+                .await?;
+        let account_keys = lookup_tables::account_keys(message, &lookup_tables)?;
+        let mut message = message.clone();
+        message.set_recent_blockhash(self.get_latest_blockhash().await?);
+        let tx = VersionedTransaction::try_new(message, signers)?;
+        let result = self
+            .simulate_transaction_with_config(
+                &tx,
+                SimulationReport::rpc_config(&account_keys, true, config),
+            )
+            .await?;
+        let message_accounts =
+            rpc_message_accounts(self, &account_keys, result.context.slot, config).await?;
+
+        Ok(SimulationReport::from_rpc(
+            result.value,
+            &account_keys,
+            message_accounts,
+            tx.message.instructions(),
+        )?)
+    }
+
+    async fn estimate_compute_units_versioned_tx<'a, I: Signers + Sync + ?Sized>(
+        &self,
+        transaction: &VersionedTransaction,
+        signers: &'a I,
+    ) -> Result<u64, Box<dyn std::error::Error + Send + Sync + 'static>> {
+        self.estimate_compute_units_versioned_tx_with_config(
+            transaction,
+            signers,
+            &LocalSimulationConfig::default(),
+        )
+        .await
+    }
+
+    async fn estimate_compute_units_versioned_tx_with_config<'a, I: Signers + Sync + ?Sized>(
+        &self,
+        transaction: &VersionedTransaction,
+        signers: &'a I,
+        config: &LocalSimulationConfig,
+    ) -> Result<u64, Box<dyn std::error::Error + Send + Sync + 'static>> {
+        let report = self
+            .simulate_versioned_tx_with_config(transaction, signers, config)
+            .await?;
+        report.check()?;
+
+        Ok(report.units_consumed)
+    }
+
+    async fn estimate_compute_units_versioned_msg<'a, I: Signers + Sync + ?Sized>(
+        &self,
+        message: &VersionedMessage,
+        signers: &'a I,
+    ) -> Result<u64, Box<dyn std::error::Error + Send + Sync + 'static>> {
+        let report = self.simulate_versioned_msg(message, signers).await?;
+        report.check()?;
+        let consumed_cu = report.units_consumed;
+
+        if consumed_cu == 0 {
+            return Err(Box::new(SolanaClientExtError::RpcError(
+                "Transaction simulation failed.".into(),
+            )));
+        }
+
+        Ok(consumed_cu)
+    }
+
+    async fn optimize_compute_units_versioned_tx<'a, I: Signers + Sync + ?Sized>(
+        &self,
+        transaction: &mut VersionedTransaction,
+        signers: &'a I,
+    ) -> Result<u32, Box<dyn std::error::Error + Send + Sync + 'static>> {
+        self.optimize_compute_units_versioned_tx_with_config(
+            transaction,
+            signers,
+            &OptimizeConfig::default(),
+        )
+        .await
+        .map(|limit| limit.units_consumed)
+    }
+
+    async fn optimize_compute_units_versioned_tx_with_config<'a, I: Signers + Sync + ?Sized>(
+        &self,
+        transaction: &mut VersionedTransaction,
+        signers: &'a I,
+        config: &OptimizeConfig,
+    ) -> Result<ComputeUnitLimit, Box<dyn std::error::Error + Send + Sync + 'static>> {
+        let optimal_cu = if config.allow_failed_transaction {
+            self.simulate_versioned_tx(transaction, signers)
+                .await?
+                .units_consumed
+        } else {
+            self.estimate_compute_units_versioned_tx(transaction, signers)
+                .await?
+        };
+        let lookup_tables = lookup_tables::load_lookup_tables_async(
+            &AsyncRpcAccountSource::new(self),
+            &transaction.message,
+        )
+        .await?;
+
+        Ok(compute_budget::set_versioned_compute_unit_limit(
+            &mut transaction.message,
+            optimal_cu,
+            &config.margin,
+            &lookup_tables,
+        )?)
+    }
+
+    async fn optimize_compute_units_versioned_msg<'a, I: Signers + Sync + ?Sized>(
+        &self,
+        message: &mut VersionedMessage,
+        signers: &'a I,
+    ) -> Result<u32, Box<dyn std::error::Error + Send + Sync + 'static>> {
+        self.optimize_compute_units_versioned_msg_with_config(
+            message,
+            signers,
+            &OptimizeConfig::default(),
+        )
+        .await
+        .map(|limit| limit.units_consumed)
+    }
+
+    /// Same as `optimize_compute_units_msg_with_config` for versioned messages.
+    /// v0 messages are compiled again with the lookup tables they reference.
+    async fn optimize_compute_units_versioned_msg_with_config<'a, I: Signers + Sync + ?Sized>(
+        &self,
+        message: &mut VersionedMessage,
+        signers: &'a I,
+        config: &OptimizeConfig,
+    ) -> Result<ComputeUnitLimit, Box<dyn std::error::Error + Send + Sync + 'static>> {
+        let optimal_cu = if config.allow_failed_transaction {
+            self.simulate_versioned_msg(message, signers)
+                .await?
+                .units_consumed
+        } else {
+            self.estimate_compute_units_versioned_msg(message, signers)
+                .await?
+        };
+        let lookup_tables =
+            lookup_tables::load_lookup_tables_async(&AsyncRpcAccountSource::new(self), message)
+                .await?;
+
+        Ok(compute_budget::set_versioned_compute_unit_limit(
+            message,
+            optimal_cu,
+            &config.margin,
+            &lookup_tables,
+        )?)
+    }
+}
+
+/// Same as the blocking `rpc_message_accounts`, with the nonblocking client.
+async fn rpc_message_accounts(
+    rpc_client: &RpcClient,
+    account_keys: &[Pubkey],
+    slot: Slot,
+    config: &RpcSimulationConfig,
+) -> Result<account_source::MessageAccounts, SolanaClientExtError> {
+    if !config.account_diffs {
+        return Ok(Default::default());
+    }
+    account_source::message_accounts_async(
+        &AsyncRpcAccountSource::with_min_context_slot(rpc_client, slot),
+        account_keys,
+    )
+    .await
+}
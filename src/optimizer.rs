@@ -0,0 +1,249 @@
+use solana_client::rpc_client::RpcClient;
+use solana_compute_budget_interface::ComputeBudgetInstruction;
+use solana_hash::Hash;
+use solana_message::Message;
+use solana_signer::signers::Signers;
+use solana_transaction::Transaction;
+
+use crate::{
+    compute_budget,
+    config::{OptimizeConfig, PriorityFeeConfig},
+    report::ComputeUnitLimit,
+    RpcClientExt,
+};
+
+/// # OptimizationSummary
+///
+/// Every decision made by [`TransactionOptimizer::build`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OptimizationSummary {
+    /// Compute units measured and the limit requested, if the limit was set.
+    pub compute_unit_limit: Option<ComputeUnitLimit>,
+    /// Compute unit price in micro-lamports, if the price was set.
+    pub compute_unit_price: Option<u64>,
+    /// Requested heap frame size in bytes, if any.
+    pub heap_frame: Option<u32>,
+    /// Requested loaded accounts data size limit in bytes, if any.
+    pub loaded_accounts_data_size_limit: Option<u32>,
+    /// Blockhash the transaction was signed with.
+    pub recent_blockhash: Hash,
+}
+
+/// # TransactionOptimizer
+///
+/// Sets every ComputeBudget instruction of a message and signs it with a fresh blockhash.
+///
+/// ```no_run
+/// use solana_client::rpc_client::RpcClient;
+/// use solana_client_ext::TransactionOptimizer;
+/// use solana_sdk::{message::Message, signature::Keypair, signer::Signer};
+/// use solana_system_interface::instruction as system_instruction;
+///
+/// let rpc_client = RpcClient::new("https://api.devnet.solana.com");
+/// let keypair = Keypair::new();
+/// let transfer_ix = system_instruction::transfer(&keypair.pubkey(), &Keypair::new().pubkey(), 10000);
+/// let msg = Message::new(&[transfer_ix], Some(&keypair.pubkey()));
+///
+/// let (tx, summary) = TransactionOptimizer::new(&rpc_client, msg, &[&keypair])
+///     .with_heap_frame(64 * 1024)
+///     .build()
+///     .unwrap();
+/// println!("{:?}", summary);
+/// rpc_client.send_and_confirm_transaction(&tx).unwrap();
+/// ```
+pub struct TransactionOptimizer<'a, S: Signers + ?Sized> {
+    rpc_client: &'a RpcClient,
+    message: Message,
+    signers: &'a S,
+    compute_unit_limit: Option<OptimizeConfig>,
+    priority_fee: Option<PriorityFeeConfig>,
+    heap_frame: Option<u32>,
+    loaded_accounts_data_size_limit: Option<u32>,
+}
+
+impl<'a, S: Signers + ?Sized> TransactionOptimizer<'a, S> {
+    /// Optimizes `message` with the default compute unit limit and priority fee configs.
+    pub fn new(rpc_client: &'a RpcClient, message: Message, signers: &'a S) -> Self {
+        Self {
+            rpc_client,
+            message,
+            signers,
+            compute_unit_limit: Some(OptimizeConfig::default()),
+            priority_fee: Some(PriorityFeeConfig::default()),
+            heap_frame: None,
+            loaded_accounts_data_size_limit: None,
+        }
+    }
+
+    pub fn with_compute_unit_limit(mut self, config: OptimizeConfig) -> Self {
+        self.compute_unit_limit = Some(config);
+        self
+    }
+
+    /// Leaves the compute unit limit of the message untouched.
+    pub fn without_compute_unit_limit(mut self) -> Self {
+        self.compute_unit_limit = None;
+        self
+    }
+
+    pub fn with_priority_fee(mut self, config: PriorityFeeConfig) -> Self {
+        self.priority_fee = Some(config);
+        self
+    }
+
+    /// Leaves the compute unit price of the message untouched.
+    pub fn without_priority_fee(mut self) -> Self {
+        self.priority_fee = None;
+        self
+    }
+
+    /// Requests a heap frame of `bytes`, which must be a multiple of 1024.
+    pub fn with_heap_frame(mut self, bytes: u32) -> Self {
+        self.heap_frame = Some(bytes);
+        self
+    }
+
+    pub fn with_loaded_accounts_data_size_limit(mut self, bytes: u32) -> Self {
+        self.loaded_accounts_data_size_limit = Some(bytes);
+        self
+    }
+
+    /// Sets the ComputeBudget instructions, then signs the message with the latest blockhash.
+    ///
+    /// The heap frame, loaded accounts data size limit and compute unit price are set
+    /// before simulating, so the measured compute units include the compute units
+    /// their instructions consume.
+    pub fn build(
+        self,
+    ) -> Result<(Transaction, OptimizationSummary), Box<dyn std::error::Error + 'static>> {
+        let mut message = self.message;
+
+        if let Some(bytes) = self.heap_frame {
+            compute_budget::set_compute_budget_instruction(
+                &mut message,
+                ComputeBudgetInstruction::request_heap_frame(bytes),
+            )?;
+        }
+        if let Some(bytes) = self.loaded_accounts_data_size_limit {
+            compute_budget::set_compute_budget_instruction(
+                &mut message,
+                ComputeBudgetInstruction::set_loaded_accounts_data_size_limit(bytes),
+            )?;
+        }
+
+        let compute_unit_price = self
+            .priority_fee
+            .map(|config| {
+                self.rpc_client
+                    .optimize_priority_fee_msg_with_config(&mut message, &config)
+            })
+            .transpose()?;
+        let compute_unit_limit = self
+            .compute_unit_limit
+            .map(|config| {
+                self.rpc_client.optimize_compute_units_msg_with_config(
+                    &mut message,
+                    self.signers,
+                    &config,
+                )
+            })
+            .transpose()?;
+
+        let recent_blockhash = self.rpc_client.get_latest_blockhash()?;
+        let mut transaction = Transaction::new_unsigned(message);
+        transaction.try_sign(self.signers, recent_blockhash)?;
+
+        Ok((
+            transaction,
+            OptimizationSummary {
+                compute_unit_limit,
+                compute_unit_price,
+                heap_frame: self.heap_frame,
+                loaded_accounts_data_size_limit: self.loaded_accounts_data_size_limit,
+                recent_blockhash,
+            },
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use solana_client::rpc_request::RpcRequest;
+    use solana_rpc_client::mock_sender::MocksMap;
+    use solana_sdk::{pubkey::Pubkey, signature::Keypair, signer::Signer};
+    use solana_system_interface::instruction as system_instruction;
+
+    use super::*;
+
+    #[test]
+    fn sets_compute_budget_instructions_then_signs() {
+        let simulation_blockhash = Hash::new_unique();
+        let recent_blockhash = Hash::new_unique();
+        let latest_blockhash = |blockhash: Hash| {
+            json!({
+                "context": { "slot": 1 },
+                "value": { "blockhash": blockhash.to_string(), "lastValidBlockHeight": 1234 },
+            })
+        };
+        let mocks = MocksMap::from_iter([
+            (
+                RpcRequest::GetRecentPrioritizationFees,
+                json!([{ "slot": 1, "prioritizationFee": 5_000 }]),
+            ),
+            (
+                RpcRequest::GetLatestBlockhash,
+                latest_blockhash(simulation_blockhash),
+            ),
+            (
+                RpcRequest::SimulateTransaction,
+                json!({
+                    "context": { "slot": 1 },
+                    "value": { "err": null, "logs": [], "unitsConsumed": 1_000 },
+                }),
+            ),
+            (
+                RpcRequest::GetLatestBlockhash,
+                latest_blockhash(recent_blockhash),
+            ),
+        ]);
+        let rpc_client = RpcClient::new_mock_with_mocks_map("succeeds", mocks);
+        let payer = Keypair::new();
+        let transfer = system_instruction::transfer(&payer.pubkey(), &Pubkey::new_unique(), 1);
+        let message = Message::new(std::slice::from_ref(&transfer), Some(&payer.pubkey()));
+
+        let (transaction, summary) = TransactionOptimizer::new(&rpc_client, message, &[&payer])
+            .with_heap_frame(64 * 1024)
+            .with_loaded_accounts_data_size_limit(32 * 1024)
+            .build()
+            .unwrap();
+
+        // Every instruction is prepended, so they end up in reverse order of insertion.
+        let expected = Message::new_with_blockhash(
+            &[
+                ComputeBudgetInstruction::set_compute_unit_limit(1_150),
+                ComputeBudgetInstruction::set_compute_unit_price(5_000),
+                ComputeBudgetInstruction::set_loaded_accounts_data_size_limit(32 * 1024),
+                ComputeBudgetInstruction::request_heap_frame(64 * 1024),
+                transfer,
+            ],
+            Some(&payer.pubkey()),
+            &recent_blockhash,
+        );
+        assert_eq!(transaction.message, expected);
+        assert!(transaction.verify().is_ok());
+        assert_eq!(
+            summary,
+            OptimizationSummary {
+                compute_unit_limit: Some(ComputeUnitLimit {
+                    units_consumed: 1_000,
+                    limit: 1_150,
+                }),
+                compute_unit_price: Some(5_000),
+                heap_frame: Some(64 * 1024),
+                loaded_accounts_data_size_limit: Some(32 * 1024),
+                recent_blockhash,
+            }
+        );
+    }
+}
@@ -0,0 +1,26 @@
+use solana_client::rpc_response::RpcPrioritizationFee;
+use solana_message::Message;
+use solana_pubkey::Pubkey;
+
+use crate::config::PriorityFeePercentile;
+
+/// Accounts write locked by `message`, which the prioritization fees are looked up for.
+pub(crate) fn writable_accounts(message: &Message) -> Vec<Pubkey> {
+    message
+        .account_keys
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| message.is_maybe_writable(*index, None))
+        .map(|(_, pubkey)| *pubkey)
+        .collect()
+}
+
+/// Returns the `percentile` of the prioritization fees paid in recent slots,
+/// in micro-lamports per compute unit.
+pub(crate) fn select(fees: &[RpcPrioritizationFee], percentile: PriorityFeePercentile) -> u64 {
+    let fees = fees
+        .iter()
+        .map(|fee| fee.prioritization_fee)
+        .collect::<Vec<_>>();
+    percentile.select(&fees)
+}
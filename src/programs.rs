@@ -0,0 +1,81 @@
+use std::collections::HashMap;
+
+use solana_account::{state_traits::StateMut, AccountSharedData, ReadableAccount};
+use solana_clock::Slot;
+use solana_loader_v3_interface::state::UpgradeableLoaderState;
+use solana_program_runtime::loaded_programs::{ProgramCacheForTxBatch, ProgramRuntimeEnvironments};
+use solana_pubkey::Pubkey;
+use solana_sdk_ids::{bpf_loader, bpf_loader_deprecated, bpf_loader_upgradeable, loader_v4};
+use solana_svm::{
+    program_loader::load_program_with_pubkey,
+    transaction_processing_callback::TransactionProcessingCallback,
+};
+use solana_svm_callback::InvokeContextCallback;
+use solana_timings::ExecuteTimings;
+
+/// Owners of accounts holding deployed sBPF programs.
+const PROGRAM_OWNERS: [Pubkey; 4] = [
+    bpf_loader_deprecated::id(),
+    bpf_loader::id(),
+    bpf_loader_upgradeable::id(),
+    loader_v4::id(),
+];
+
+/// Accounts fetched for a transaction, exposed to the SVM program loader.
+pub(crate) struct ProgramAccounts<'a>(pub &'a HashMap<Pubkey, AccountSharedData>);
+
+impl InvokeContextCallback for ProgramAccounts<'_> {}
+
+impl TransactionProcessingCallback for ProgramAccounts<'_> {
+    fn account_matches_owners(&self, account: &Pubkey, owners: &[Pubkey]) -> Option<usize> {
+        let account = self.0.get(account)?;
+        owners.iter().position(|owner| account.owner() == owner)
+    }
+
+    fn get_account_shared_data(&self, pubkey: &Pubkey) -> Option<AccountSharedData> {
+        self.0.get(pubkey).cloned()
+    }
+}
+
+/// Returns the ProgramData addresses of the upgradeable programs among `accounts`.
+/// These are not part of the message but hold the executable bytes of the program.
+pub(crate) fn programdata_addresses(accounts: &[(Pubkey, AccountSharedData)]) -> Vec<Pubkey> {
+    accounts
+        .iter()
+        .filter(|(_, account)| bpf_loader_upgradeable::check_id(account.owner()))
+        .filter_map(|(_, account)| match account.state() {
+            Ok(UpgradeableLoaderState::Program {
+                programdata_address,
+            }) => Some(programdata_address),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Verifies every sBPF program referenced by `keys` and inserts it into the program cache.
+///
+/// `accounts` must contain the program accounts and, for upgradeable programs,
+/// their ProgramData accounts.
+pub(crate) fn load_programs(
+    keys: &[Pubkey],
+    accounts: &HashMap<Pubkey, AccountSharedData>,
+    environments: &ProgramRuntimeEnvironments,
+    slot: Slot,
+    program_cache: &mut ProgramCacheForTxBatch,
+) {
+    let callbacks = ProgramAccounts(accounts);
+    let mut timings = ExecuteTimings::default();
+    for key in keys {
+        let is_program = accounts
+            .get(key)
+            .is_some_and(|account| PROGRAM_OWNERS.contains(account.owner()));
+        if !is_program {
+            continue;
+        }
+        if let Some(program) =
+            load_program_with_pubkey(&callbacks, environments, key, slot, &mut timings, false)
+        {
+            program_cache.replenish(*key, program);
+        }
+    }
+}
@@ -0,0 +1,325 @@
+use base64::{prelude::BASE64_STANDARD, Engine};
+use std::ops::Range;
+
+use solana_account::{AccountSharedData, ReadableAccount};
+use solana_account_decoder_client_types::UiAccountEncoding;
+use solana_client::{
+    rpc_config::{RpcSimulateTransactionAccountsConfig, RpcSimulateTransactionConfig},
+    rpc_response::RpcSimulateTransactionResult,
+};
+use solana_message::compiled_instruction::CompiledInstruction;
+use solana_pubkey::Pubkey;
+use solana_transaction_context::TransactionReturnData;
+use solana_transaction_error::TransactionResult;
+use solana_transaction_status_client_types::UiInnerInstructions;
+
+use crate::{
+    account_source::MessageAccounts,
+    config::RpcSimulationConfig,
+    error::SolanaClientExtError,
+    invocation::{self, InvocationNode},
+    token::{self, TokenBalanceChange},
+};
+
+/// # SimulationReport
+///
+/// Outcome of simulating a transaction, either locally or through the RPC node.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SimulationReport {
+    /// Compute units consumed by the transaction.
+    pub units_consumed: u64,
+    /// `Ok(())` if every instruction succeeded.
+    pub result: TransactionResult<()>,
+    /// Program logs emitted during execution.
+    pub logs: Vec<String>,
+    /// Data set by the last program calling `set_return_data`, if any.
+    pub return_data: Option<TransactionReturnData>,
+    /// Instructions invoked through CPI, grouped by top level instruction.
+    pub inner_instructions: Vec<UiInnerInstructions>,
+    /// Compute units consumed by every instruction, top level and CPI, in invocation order.
+    pub instruction_compute_units: Vec<InstructionComputeUnits>,
+    /// Executed top level instructions, with the instructions they invoked through CPI.
+    pub invocations: Vec<InvocationNode>,
+    /// State of the message accounts after execution.
+    /// Accounts that don't exist after execution are default accounts.
+    ///
+    /// RPC simulations only report accounts, account diffs and token balance changes
+    /// if [`RpcSimulationConfig::account_diffs`] is set.
+    pub accounts: Vec<(Pubkey, AccountSharedData)>,
+    /// Changes made to the message accounts, in message order.
+    pub account_diffs: Vec<AccountDiff>,
+    /// SPL Token and Token-2022 balances which changed, in message order.
+    pub token_balance_changes: Vec<TokenBalanceChange>,
+}
+
+impl SimulationReport {
+    pub fn is_success(&self) -> bool {
+        self.result.is_ok()
+    }
+
+    /// Returns the execution error of the transaction, if it failed.
+    pub fn check(&self) -> Result<(), SolanaClientExtError> {
+        self.result.clone().map_err(SolanaClientExtError::from)
+    }
+
+    /// Simulation config asking the RPC node for everything [`SimulationReport::from_rpc`] reads.
+    ///
+    /// Without `sig_verify` the node replaces the blockhash of the transaction,
+    /// so unsigned transactions can be simulated. The state of `account_keys` after
+    /// the simulation is only asked for if `config` reports account diffs.
+    pub(crate) fn rpc_config(
+        account_keys: &[Pubkey],
+        sig_verify: bool,
+        config: &RpcSimulationConfig,
+    ) -> RpcSimulateTransactionConfig {
+        RpcSimulateTransactionConfig {
+            sig_verify,
+            replace_recent_blockhash: !sig_verify,
+            accounts: config
+                .account_diffs
+                .then(|| RpcSimulateTransactionAccountsConfig {
+                    encoding: Some(UiAccountEncoding::Base64),
+                    addresses: account_keys.iter().map(ToString::to_string).collect(),
+                }),
+            inner_instructions: true,
+            ..RpcSimulateTransactionConfig::default()
+        }
+    }
+
+    /// Builds a report from an RPC simulation of a message with `account_keys`
+    /// and the top level `instructions`.
+    ///
+    /// `pre_accounts` hold the state of the message accounts before the simulation
+    /// if the simulation was asked for their state after it, and are empty otherwise.
+    /// `mints` hold the mints of token accounts which aren't message accounts.
+    pub(crate) fn from_rpc(
+        result: RpcSimulateTransactionResult,
+        account_keys: &[Pubkey],
+        (pre_accounts, mints): MessageAccounts,
+        instructions: &[CompiledInstruction],
+    ) -> Result<Self, SolanaClientExtError> {
+        let units_consumed = result.units_consumed.ok_or_else(|| {
+            SolanaClientExtError::ComputeUnitsError(
+                "Missing Compute Units from transaction simulation.".into(),
+            )
+        })?;
+
+        let return_data = result
+            .return_data
+            .map(|return_data| {
+                let program_id = return_data.program_id.parse().map_err(|_| {
+                    SolanaClientExtError::RpcError("Invalid return data program id.".into())
+                })?;
+                let data = BASE64_STANDARD.decode(return_data.data.0).map_err(|_| {
+                    SolanaClientExtError::RpcError("Invalid return data encoding.".into())
+                })?;
+                Ok::<_, SolanaClientExtError>(TransactionReturnData { program_id, data })
+            })
+            .transpose()?;
+
+        let accounts = account_keys
+            .iter()
+            .zip(result.accounts.unwrap_or_default())
+            .map(|(pubkey, account)| {
+                let account = account
+                    .map(|account| {
+                        account.decode().ok_or_else(|| {
+                            SolanaClientExtError::RpcError(format!(
+                                "Unable to decode simulated account {pubkey}."
+                            ))
+                        })
+                    })
+                    .transpose()?
+                    .unwrap_or_default();
+                Ok((*pubkey, account))
+            })
+            .collect::<Result<Vec<_>, SolanaClientExtError>>()?;
+
+        let logs = result.logs.unwrap_or_default();
+        let transaction_result = result.err.map_or(Ok(()), Err);
+        let inner_instructions = result.inner_instructions.unwrap_or_default();
+        let mut invocations = invocation::rpc_invocations(
+            instructions,
+            account_keys,
+            &inner_instructions,
+            &transaction_result,
+        );
+        invocation::annotate_invocations(&mut invocations, &logs, &transaction_result);
+        Ok(Self {
+            units_consumed,
+            instruction_compute_units: invocation::instruction_compute_units(&invocations),
+            invocations: invocation::invocation_tree(invocations),
+            result: transaction_result,
+            logs,
+            return_data,
+            inner_instructions,
+            account_diffs: account_diffs(&pre_accounts, &accounts),
+            token_balance_changes: token::balance_changes(&pre_accounts, &accounts, &mints),
+            accounts,
+        })
+    }
+}
+
+/// # AccountDiff
+///
+/// Changes made to an account by a simulated transaction.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AccountDiff {
+    pub pubkey: Pubkey,
+    pub status: AccountStatus,
+    /// Lamports after execution minus lamports before, including the fee charged
+    /// to the fee payer.
+    pub lamports_delta: i128,
+    /// Byte ranges of the data that changed. Bytes past the end of the shorter data count
+    /// as changed, so resizing the data shows up as a range at its end.
+    pub data_changes: Vec<Range<usize>>,
+    /// Owner before and after execution, if it changed.
+    pub owner_change: Option<(Pubkey, Pubkey)>,
+}
+
+/// # AccountStatus
+///
+/// Whether an account was created, closed or modified by a simulated transaction.
+/// Accounts without lamports don't exist.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccountStatus {
+    Created,
+    Closed,
+    Modified,
+}
+
+impl AccountDiff {
+    /// Compares the state of the account at `pubkey` before and after execution,
+    /// returning `None` if it didn't change.
+    pub fn between(
+        pubkey: Pubkey,
+        before: &AccountSharedData,
+        after: &AccountSharedData,
+    ) -> Option<Self> {
+        let status = match (before.lamports(), after.lamports()) {
+            (0, 0) => return None,
+            (0, _) => AccountStatus::Created,
+            (_, 0) => AccountStatus::Closed,
+            _ => AccountStatus::Modified,
+        };
+        let lamports_delta = i128::from(after.lamports()) - i128::from(before.lamports());
+        let data_changes = data_changes(before.data(), after.data());
+        let owner_change =
+            (before.owner() != after.owner()).then(|| (*before.owner(), *after.owner()));
+        if lamports_delta == 0 && data_changes.is_empty() && owner_change.is_none() {
+            return None;
+        }
+        Some(Self {
+            pubkey,
+            status,
+            lamports_delta,
+            data_changes,
+            owner_change,
+        })
+    }
+}
+
+/// Diffs of the accounts which changed between `before` and `after`,
+/// both holding the same accounts in the same order.
+pub(crate) fn account_diffs(
+    before: &[(Pubkey, AccountSharedData)],
+    after: &[(Pubkey, AccountSharedData)],
+) -> Vec<AccountDiff> {
+    before
+        .iter()
+        .zip(after)
+        .filter_map(|((pubkey, before), (_, after))| AccountDiff::between(*pubkey, before, after))
+        .collect()
+}
+
+fn data_changes(before: &[u8], after: &[u8]) -> Vec<Range<usize>> {
+    let mut changes: Vec<Range<usize>> = Vec::new();
+    for index in 0..before.len().max(after.len()) {
+        if before.get(index) == after.get(index) {
+            continue;
+        }
+        match changes.last_mut() {
+            Some(range) if range.end == index => range.end += 1,
+            _ => changes.push(index..index + 1),
+        }
+    }
+    changes
+}
+
+/// # InstructionComputeUnits
+///
+/// Compute units consumed by a single instruction of a simulated transaction.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InstructionComputeUnits {
+    /// Index of the top level instruction this instruction was invoked from.
+    pub instruction_index: usize,
+    pub program_id: Pubkey,
+    /// 1 for top level instructions, incremented by every CPI.
+    pub stack_height: usize,
+    /// Compute units consumed by the instruction, including the CPIs it made.
+    /// `None` if they are unknown, builtin programs don't log their consumption
+    /// so it is only known for top level builtin instructions executed locally.
+    pub units_consumed: Option<u64>,
+}
+
+/// # ComputeUnitLimit
+///
+/// Compute unit limit set by the `optimize_compute_units_*_with_config` functions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ComputeUnitLimit {
+    /// Compute units consumed by the simulated transaction.
+    pub units_consumed: u32,
+    /// Compute unit limit requested by the transaction.
+    pub limit: u32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rpc_config_asks_for_accounts_with_diffs() {
+        let account_keys = [Pubkey::new_unique()];
+        let config = SimulationReport::rpc_config(&account_keys, true, &Default::default());
+        assert!(config.accounts.is_none());
+
+        let config = SimulationReport::rpc_config(
+            &account_keys,
+            true,
+            &RpcSimulationConfig {
+                account_diffs: true,
+            },
+        );
+        assert_eq!(
+            config.accounts.unwrap().addresses,
+            [account_keys[0].to_string()]
+        );
+    }
+
+    #[test]
+    fn diffs_account_changes() {
+        let owner = Pubkey::new_unique();
+        let before = AccountSharedData::from(solana_account::Account {
+            lamports: 1_000,
+            data: vec![0, 1, 2, 3],
+            owner,
+            ..solana_account::Account::default()
+        });
+        let after = AccountSharedData::from(solana_account::Account {
+            lamports: 400,
+            data: vec![9, 1, 2, 8, 8],
+            owner,
+            ..solana_account::Account::default()
+        });
+
+        let diff = AccountDiff::between(Pubkey::new_unique(), &before, &after).unwrap();
+        assert_eq!(diff.status, AccountStatus::Modified);
+        assert_eq!(diff.lamports_delta, -600);
+        assert_eq!(diff.data_changes, vec![0..1, 3..5]);
+        assert_eq!(diff.owner_change, None);
+
+        let closed = AccountDiff::between(diff.pubkey, &after, &AccountSharedData::default());
+        assert_eq!(closed.unwrap().status, AccountStatus::Closed);
+        assert_eq!(AccountDiff::between(diff.pubkey, &before, &before), None);
+    }
+}
@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+
+use solana_account::{AccountSharedData, ReadableAccount};
+use solana_client::{nonblocking, rpc_client::RpcClient};
+use solana_clock::Clock;
+use solana_program_runtime::sysvar_cache::SysvarCache;
+use solana_pubkey::Pubkey;
+use solana_sdk_ids::sysvar;
+
+use crate::{
+    account_source::{AccountSource, AsyncAccountSource, AsyncRpcAccountSource, RpcAccountSource},
+    error::SolanaClientExtError,
+};
+
+/// Sysvars read by programs through the `SysvarCache`.
+///
+/// `recent_blockhashes` is deprecated but still read by the system program
+/// to advance durable nonces.
+pub const SYSVAR_IDS: [Pubkey; 8] = [
+    sysvar::clock::id(),
+    sysvar::epoch_schedule::id(),
+    sysvar::epoch_rewards::id(),
+    sysvar::rent::id(),
+    sysvar::slot_hashes::id(),
+    sysvar::stake_history::id(),
+    sysvar::last_restart_slot::id(),
+    sysvar::recent_blockhashes::id(),
+];
+
+/// # Sysvars
+///
+/// Snapshot of the sysvar accounts the local estimator exposes to programs.
+/// Either fetched from the cluster with [`Sysvars::fetch`] or provided by the caller.
+#[derive(Clone, Debug, Default)]
+pub struct Sysvars {
+    accounts: HashMap<Pubkey, AccountSharedData>,
+}
+
+impl Sysvars {
+    pub fn new(accounts: impl IntoIterator<Item = (Pubkey, AccountSharedData)>) -> Self {
+        Self {
+            accounts: accounts.into_iter().collect(),
+        }
+    }
+
+    /// Fetches every sysvar in [`SYSVAR_IDS`] from the cluster.
+    /// Sysvars the cluster doesn't have are left out of the snapshot.
+    pub fn fetch(rpc_client: &RpcClient) -> Result<Self, SolanaClientExtError> {
+        Self::load(&RpcAccountSource::new(rpc_client))
+    }
+
+    /// Reads every sysvar in [`SYSVAR_IDS`] from `source`.
+    /// Sysvars `source` doesn't hold are left out of the snapshot.
+    pub fn load(source: &(impl AccountSource + ?Sized)) -> Result<Self, SolanaClientExtError> {
+        Ok(Self::from_accounts(source.get_accounts(&SYSVAR_IDS)?))
+    }
+
+    /// Same as [`Sysvars::fetch`], with the nonblocking client.
+    pub async fn fetch_async(
+        rpc_client: &nonblocking::rpc_client::RpcClient,
+    ) -> Result<Self, SolanaClientExtError> {
+        let source = AsyncRpcAccountSource::new(rpc_client);
+        Ok(Self::from_accounts(source.get_accounts(&SYSVAR_IDS).await?))
+    }
+
+    /// Builds the snapshot from the accounts read at [`SYSVAR_IDS`].
+    pub(crate) fn from_accounts(accounts: Vec<Option<AccountSharedData>>) -> Self {
+        Self::new(
+            SYSVAR_IDS
+                .into_iter()
+                .zip(accounts)
+                .filter_map(|(pubkey, account)| Some((pubkey, account?))),
+        )
+    }
+
+    pub fn get(&self, pubkey: &Pubkey) -> Option<&AccountSharedData> {
+        self.accounts.get(pubkey)
+    }
+
+    pub fn insert(&mut self, pubkey: Pubkey, account: AccountSharedData) {
+        self.accounts.insert(pubkey, account);
+    }
+
+    /// Returns the clock sysvar, or the default clock if it's missing.
+    pub(crate) fn clock(&self) -> Clock {
+        self.sysvar_cache()
+            .get_clock()
+            .map(|clock| (*clock).clone())
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn sysvar_cache(&self) -> SysvarCache {
+        let mut sysvar_cache = SysvarCache::default();
+        sysvar_cache.fill_missing_entries(|pubkey, set_sysvar| {
+            if let Some(account) = self.accounts.get(pubkey) {
+                set_sysvar(account.data());
+            }
+        });
+        sysvar_cache
+    }
+}
@@ -0,0 +1,248 @@
+//! Decoding of SPL Token and Token-2022 accounts, to preview token balance changes.
+
+use std::collections::HashMap;
+
+use solana_account::{AccountSharedData, ReadableAccount};
+use solana_account_decoder_client_types::token::{real_number_string_trimmed, UiTokenAmount};
+use solana_pubkey::{pubkey, Pubkey};
+use solana_transaction_status_client_types::{
+    option_serializer::OptionSerializer, UiTransactionTokenBalance,
+};
+
+pub const TOKEN_PROGRAM_ID: Pubkey = pubkey!("TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA");
+pub const TOKEN_2022_PROGRAM_ID: Pubkey = pubkey!("TokenzQdBNbLqP5VEhdkAbS5EqHcR5rKdN1b9pZgF4c");
+
+/// Length of a token account without extensions.
+const ACCOUNT_LEN: usize = 165;
+/// Length of a mint without extensions.
+const MINT_LEN: usize = 82;
+/// Offset of the Token-2022 account type, which follows the base account for accounts
+/// with extensions. Mints with extensions are padded to the same offset.
+const ACCOUNT_TYPE_OFFSET: usize = ACCOUNT_LEN;
+const ACCOUNT_TYPE_MINT: u8 = 1;
+const ACCOUNT_TYPE_ACCOUNT: u8 = 2;
+const AMOUNT_OFFSET: usize = 64;
+const STATE_OFFSET: usize = 108;
+const DECIMALS_OFFSET: usize = 44;
+const MINT_INITIALIZED_OFFSET: usize = 45;
+
+/// # TokenBalanceChange
+///
+/// Token balance of a token account before and after a simulated transaction.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TokenBalanceChange {
+    /// Index of the token account in the message accounts.
+    pub account_index: usize,
+    pub account: Pubkey,
+    pub mint: Pubkey,
+    /// Owner of the tokens, not the program owning the token account.
+    pub owner: Pubkey,
+    /// SPL Token or Token-2022.
+    pub program_id: Pubkey,
+    pub decimals: u8,
+    /// `None` if the account wasn't a token account before execution.
+    pub pre_amount: Option<u64>,
+    /// `None` if the account isn't a token account after execution.
+    pub post_amount: Option<u64>,
+}
+
+impl TokenBalanceChange {
+    /// Amount received by the account, negative if tokens were sent.
+    pub fn delta(&self) -> i128 {
+        i128::from(self.post_amount.unwrap_or_default())
+            - i128::from(self.pre_amount.unwrap_or_default())
+    }
+
+    /// The balance before execution, as listed in `preTokenBalances`.
+    pub fn pre_token_balance(&self) -> Option<UiTransactionTokenBalance> {
+        self.pre_amount.map(|amount| self.ui_token_balance(amount))
+    }
+
+    /// The balance after execution, as listed in `postTokenBalances`.
+    pub fn post_token_balance(&self) -> Option<UiTransactionTokenBalance> {
+        self.post_amount.map(|amount| self.ui_token_balance(amount))
+    }
+
+    fn ui_token_balance(&self, amount: u64) -> UiTransactionTokenBalance {
+        UiTransactionTokenBalance {
+            account_index: self.account_index as u8,
+            mint: self.mint.to_string(),
+            ui_token_amount: UiTokenAmount {
+                ui_amount: Some(amount as f64 / 10_f64.powi(i32::from(self.decimals))),
+                decimals: self.decimals,
+                amount: amount.to_string(),
+                ui_amount_string: real_number_string_trimmed(amount, self.decimals),
+            },
+            owner: OptionSerializer::Some(self.owner.to_string()),
+            program_id: OptionSerializer::Some(self.program_id.to_string()),
+        }
+    }
+}
+
+/// Fields of an initialized token account.
+struct TokenAccount {
+    mint: Pubkey,
+    owner: Pubkey,
+    amount: u64,
+    program_id: Pubkey,
+}
+
+fn is_token_program(program_id: &Pubkey) -> bool {
+    *program_id == TOKEN_PROGRAM_ID || *program_id == TOKEN_2022_PROGRAM_ID
+}
+
+/// Returns true if `data` has the layout of the given account type.
+fn has_layout(program_id: &Pubkey, data: &[u8], base_len: usize, account_type: u8) -> bool {
+    data.len() == base_len
+        || (*program_id == TOKEN_2022_PROGRAM_ID
+            && data.len() > ACCOUNT_TYPE_OFFSET
+            && data[ACCOUNT_TYPE_OFFSET] == account_type)
+}
+
+fn read_pubkey(data: &[u8], offset: usize) -> Pubkey {
+    Pubkey::try_from(&data[offset..offset + 32]).unwrap_or_default()
+}
+
+fn decode_token_account(account: &AccountSharedData) -> Option<TokenAccount> {
+    let program_id = *account.owner();
+    let data = account.data();
+    if !is_token_program(&program_id)
+        || !has_layout(&program_id, data, ACCOUNT_LEN, ACCOUNT_TYPE_ACCOUNT)
+        || data[STATE_OFFSET] == 0
+    {
+        return None;
+    }
+    let amount = data[AMOUNT_OFFSET..AMOUNT_OFFSET + 8].try_into().ok()?;
+    Some(TokenAccount {
+        mint: read_pubkey(data, 0),
+        owner: read_pubkey(data, 32),
+        amount: u64::from_le_bytes(amount),
+        program_id,
+    })
+}
+
+/// Decimals of an initialized mint.
+fn decode_mint_decimals(account: &AccountSharedData) -> Option<u8> {
+    let program_id = account.owner();
+    let data = account.data();
+    if !is_token_program(program_id)
+        || !has_layout(program_id, data, MINT_LEN, ACCOUNT_TYPE_MINT)
+        || data[MINT_INITIALIZED_OFFSET] == 0
+    {
+        return None;
+    }
+    Some(data[DECIMALS_OFFSET])
+}
+
+/// Mints of the token accounts among `accounts` which aren't part of `accounts` themselves.
+/// They have to be fetched to read their decimals.
+pub(crate) fn missing_mints(accounts: &[(Pubkey, AccountSharedData)]) -> Vec<Pubkey> {
+    let mut mints = accounts
+        .iter()
+        .filter_map(|(_, account)| decode_token_account(account))
+        .map(|token_account| token_account.mint)
+        .filter(|mint| !accounts.iter().any(|(pubkey, _)| pubkey == mint))
+        .collect::<Vec<_>>();
+    mints.sort_unstable();
+    mints.dedup();
+    mints
+}
+
+/// Token balances which changed between `pre_accounts` and `post_accounts`,
+/// both holding the message accounts in message order.
+///
+/// Mint decimals are read from the message accounts, then from `mints`.
+/// Token accounts whose mint can't be read are left out.
+pub(crate) fn balance_changes(
+    pre_accounts: &[(Pubkey, AccountSharedData)],
+    post_accounts: &[(Pubkey, AccountSharedData)],
+    mints: &HashMap<Pubkey, AccountSharedData>,
+) -> Vec<TokenBalanceChange> {
+    let decimals = |mint: &Pubkey| {
+        post_accounts
+            .iter()
+            .chain(pre_accounts)
+            .filter(|(pubkey, _)| pubkey == mint)
+            .map(|(_, account)| account)
+            .chain(mints.get(mint))
+            .find_map(decode_mint_decimals)
+    };
+
+    pre_accounts
+        .iter()
+        .zip(post_accounts)
+        .enumerate()
+        .filter_map(|(account_index, ((pubkey, pre), (_, post)))| {
+            let pre = decode_token_account(pre);
+            let post = decode_token_account(post);
+            let token_account = post.as_ref().or(pre.as_ref())?;
+            let pre_amount = pre.as_ref().map(|pre| pre.amount);
+            let post_amount = post.as_ref().map(|post| post.amount);
+            if pre_amount == post_amount {
+                return None;
+            }
+            Some(TokenBalanceChange {
+                account_index,
+                account: *pubkey,
+                mint: token_account.mint,
+                owner: token_account.owner,
+                program_id: token_account.program_id,
+                decimals: decimals(&token_account.mint)?,
+                pre_amount,
+                post_amount,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_account::WritableAccount;
+
+    use super::*;
+
+    fn token_account(mint: &Pubkey, owner: &Pubkey, amount: u64) -> AccountSharedData {
+        let mut data = vec![0; ACCOUNT_LEN];
+        data[..32].copy_from_slice(mint.as_ref());
+        data[32..64].copy_from_slice(owner.as_ref());
+        data[AMOUNT_OFFSET..AMOUNT_OFFSET + 8].copy_from_slice(&amount.to_le_bytes());
+        data[STATE_OFFSET] = 1;
+        AccountSharedData::create(2_039_280, data, TOKEN_PROGRAM_ID, false, 0)
+    }
+
+    #[test]
+    fn token_balance_changes() {
+        let mint = Pubkey::new_unique();
+        let mut mint_data = vec![0; MINT_LEN];
+        mint_data[DECIMALS_OFFSET] = 6;
+        mint_data[MINT_INITIALIZED_OFFSET] = 1;
+        let mints = HashMap::from([(
+            mint,
+            AccountSharedData::create(1_461_600, mint_data, TOKEN_PROGRAM_ID, false, 0),
+        )]);
+        let (sender, recipient) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let owner = Pubkey::new_unique();
+        let pre_accounts = [
+            (sender, token_account(&mint, &owner, 5_000_000)),
+            (recipient, AccountSharedData::default()),
+        ];
+        let post_accounts = [
+            (sender, token_account(&mint, &owner, 3_500_000)),
+            (recipient, token_account(&mint, &recipient, 1_500_000)),
+        ];
+        assert_eq!(missing_mints(&pre_accounts), vec![mint]);
+
+        let changes = balance_changes(&pre_accounts, &post_accounts, &mints);
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0].delta(), -1_500_000);
+        assert_eq!(changes[1].pre_amount, None);
+        assert_eq!(
+            changes[0]
+                .post_token_balance()
+                .unwrap()
+                .ui_token_amount
+                .ui_amount_string,
+            "3.5"
+        );
+    }
+}